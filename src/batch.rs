@@ -0,0 +1,422 @@
+use crate::batch_api::{self, BATCH_CANCEL, BATCH_FETCH, BATCH_STATUS, BATCH_SUBMIT};
+use crate::cache::{CacheStatus, ResponseCache};
+use crate::chat::{split_params, ChatMessageRole, ChatUsage, OpenAiChatRequestBody};
+use crate::constants::{DEFAULT_MODEL, DEFAULT_SYSTEM_PROMPT, GPT_API_URL};
+use crate::error::{error_json, GptError};
+use crate::options::CliOptions;
+use crate::output::{print_json, print_warning, OutputFormat};
+use crate::usage::{self, UsageLedger};
+use crate::utils::{cached_chat_completion, chat_usage, prepare_chat_request};
+use colored::Colorize;
+use futures::stream::{self, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+use tokio::task::JoinHandle;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BatchInput {
+    pub id: Option<Value>,
+    pub prompt: Option<String>,
+    pub messages: Option<Vec<ChatMessageRole>>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchResult {
+    pub id: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+#[derive(Debug)]
+pub struct BatchJob {
+    pub id: String,
+    pub request: OpenAiChatRequestBody,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl BatchInput {
+    fn into_job(self, line: usize, options: &CliOptions) -> Result<BatchJob, GptError> {
+        let invalid = |message: String| {
+            GptError::invalid_input(format!("Invalid batch input at line {}: {}", line, message))
+        };
+        let id = match self.id {
+            None => line.to_string(),
+            Some(Value::String(id)) => id,
+            Some(id) => id.to_string(),
+        };
+        let messages = match (self.messages, self.prompt) {
+            (Some(messages), None) if !messages.is_empty() => messages,
+            (None, Some(prompt)) => vec![
+                ChatMessageRole {
+                    role: "system".to_string(),
+                    content: DEFAULT_SYSTEM_PROMPT.to_string(),
+                },
+                ChatMessageRole {
+                    role: "user".to_string(),
+                    content: prompt,
+                },
+            ],
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "use either prompt or messages, not both".to_string(),
+                ))
+            }
+            _ => return Err(invalid("expected a prompt or messages".to_string())),
+        };
+        let (max_tokens, params) = split_params(self.params).map_err(invalid)?;
+        let model = self
+            .model
+            .or_else(|| options.model.clone())
+            .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+
+        Ok(BatchJob {
+            id,
+            request: OpenAiChatRequestBody {
+                model,
+                messages,
+                stream: false,
+                max_tokens,
+                stream_options: None,
+                response_format: None,
+                params,
+            },
+        })
+    }
+}
+
+pub fn parse_batch(contents: &str, options: &CliOptions) -> Result<Vec<BatchJob>, GptError> {
+    let mut ids = HashSet::new();
+    let mut jobs = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let input: BatchInput = serde_json::from_str(line).map_err(|e| {
+            GptError::invalid_input(format!("Invalid batch input at line {}: {}", index + 1, e))
+        })?;
+        let job = input.into_job(index + 1, options)?;
+        if !ids.insert(job.id.clone()) {
+            return Err(GptError::invalid_input(format!(
+                "Invalid batch input at line {}: duplicate id {}",
+                index + 1,
+                job.id
+            )));
+        }
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+// Lines that failed or were cut off by an interrupted run are left out, so they run again.
+pub fn read_completed(path: &Path) -> Result<HashMap<String, BatchResult>, Box<dyn Error>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => {
+            return Err(GptError::io(format!("Failed to read {}: {}", path.display(), e)).into())
+        }
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<BatchResult>(line).ok())
+        .filter(|result| result.error.is_none())
+        .map(|result| (result.id.clone(), result))
+        .collect())
+}
+
+async fn run_job(
+    client: &Client,
+    api_url: &str,
+    mut request: OpenAiChatRequestBody,
+    options: &CliOptions,
+    ledger: &UsageLedger,
+    cache: Option<&ResponseCache>,
+) -> Result<(String, ChatUsage, f64), Box<dyn Error>> {
+    ledger.check_budget(options.budget, usage::now())?;
+    prepare_chat_request(&mut request, options)?;
+    let (completion, status) =
+        cached_chat_completion(client, api_url, &request, options, cache).await?;
+    let content = completion
+        .choices
+        .first()
+        .and_then(|c| c.message.content.clone())
+        .unwrap_or_default();
+    let usage = chat_usage(
+        completion.usage,
+        &content,
+        &request.model,
+        &request.messages,
+    );
+    let mut entry = ledger.chat_entry(&request.model, &usage);
+    if status == CacheStatus::Hit {
+        entry.cost = 0.0;
+    } else if let Err(e) = ledger.record(&entry) {
+        print_warning(&format!("Failed to record usage: {}", e));
+    }
+    Ok((content, usage, entry.cost))
+}
+
+fn batch_result(
+    job: &BatchJob,
+    result: Result<(String, ChatUsage, f64), Box<dyn Error>>,
+) -> BatchResult {
+    let mut batch_result = BatchResult {
+        id: job.id.clone(),
+        model: job.request.model.clone(),
+        content: None,
+        usage: None,
+        cost: None,
+        error: None,
+    };
+    match result {
+        Ok((content, usage, cost)) => {
+            batch_result.content = Some(content);
+            batch_result.usage = Some(usage);
+            batch_result.cost = Some(cost);
+        }
+        Err(e) => batch_result.error = Some(error_json(e.as_ref())["error"].take()),
+    }
+    batch_result
+}
+
+struct BatchProgress {
+    overall: ProgressBar,
+    slots: Vec<ProgressBar>,
+    idle: Mutex<Vec<ProgressBar>>,
+    join: JoinHandle<io::Result<()>>,
+}
+
+impl BatchProgress {
+    fn new(total: usize, done: usize, workers: usize) -> Self {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(total as u64));
+        overall.set_style(
+            ProgressStyle::default_bar()
+                .template("{bar:30.green/white} {pos}/{len} {msg} ({eta})")
+                .progress_chars("━━─"),
+        );
+        overall.set_position(done as u64);
+        let slots: Vec<ProgressBar> = (0..workers)
+            .map(|_| {
+                let slot = multi.add(ProgressBar::new_spinner());
+                slot.set_style(
+                    ProgressStyle::default_spinner()
+                        .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏", " "])
+                        .template("{spinner:.green} {msg}"),
+                );
+                slot.enable_steady_tick(100);
+                slot
+            })
+            .collect();
+        // MultiProgress only draws while something is joined on it.
+        let join = tokio::task::spawn_blocking(move || multi.join_and_clear());
+        BatchProgress {
+            overall,
+            idle: Mutex::new(slots.clone()),
+            slots,
+            join,
+        }
+    }
+
+    fn start(&self, id: &str) -> Option<ProgressBar> {
+        let slot = self.idle.lock().ok()?.pop()?;
+        slot.set_message(format!("Processing {}...", id));
+        Some(slot)
+    }
+
+    fn finish(&self, slot: Option<ProgressBar>, failed: usize) {
+        if let Some(slot) = slot {
+            slot.set_message(String::new());
+            if let Ok(mut idle) = self.idle.lock() {
+                idle.push(slot);
+            }
+        }
+        if failed > 0 {
+            self.overall.set_message(format!("{} failed", failed));
+        }
+        self.overall.inc(1);
+    }
+
+    async fn close(self) {
+        for slot in &self.slots {
+            slot.finish_and_clear();
+        }
+        self.overall.finish_and_clear();
+        let _ = self.join.await;
+    }
+}
+
+pub fn write_results(path: &Path, results: &[BatchResult]) -> Result<(), Box<dyn Error>> {
+    let temp_path = path.with_extension("jsonl.tmp");
+    let write = || -> io::Result<()> {
+        let mut file = fs::File::create(&temp_path)?;
+        for result in results {
+            writeln!(file, "{}", serde_json::to_string(result)?)?;
+        }
+        fs::rename(&temp_path, path)
+    };
+    write().map_err(|e| GptError::io(format!("Failed to write {}: {}", path.display(), e)))?;
+    Ok(())
+}
+
+pub async fn process_batch_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    match args.get(2).map(String::as_str) {
+        Some(BATCH_SUBMIT | BATCH_STATUS | BATCH_FETCH | BATCH_CANCEL) => {
+            return batch_api::process_batch_api_command(client, args, options).await
+        }
+        Some(input) => {
+            let summary = run_batch(client, GPT_API_URL, Path::new(input), options).await?;
+            report_summary(summary, options)
+        }
+        None => Err(GptError::invalid_input(
+            "Usage: gpt batch <input.jsonl> [--concurrency <n>] [--out <file>], or gpt batch submit|status|fetch|cancel",
+        )
+        .into()),
+    }
+}
+
+pub async fn run_batch(
+    client: &Client,
+    api_url: &str,
+    input: &Path,
+    options: &CliOptions,
+) -> Result<BatchSummary, Box<dyn Error>> {
+    let contents = fs::read_to_string(input)
+        .map_err(|e| GptError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let jobs = parse_batch(&contents, options)?;
+    let completed = match &options.out {
+        Some(path) => read_completed(path)?,
+        None => HashMap::new(),
+    };
+    let ledger = UsageLedger::open(options)?;
+    ledger.check_budget(options.budget, usage::now())?;
+    for job in &jobs {
+        ledger.check_priced(options.budget, &job.request.model)?;
+    }
+    let cache = ResponseCache::open(options)?;
+
+    let mut results: Vec<Option<BatchResult>> = jobs
+        .iter()
+        .map(|job| completed.get(&job.id).cloned())
+        .collect();
+    let pending: Vec<usize> = (0..jobs.len()).filter(|&i| results[i].is_none()).collect();
+    let mut summary = BatchSummary {
+        skipped: jobs.len() - pending.len(),
+        ..BatchSummary::default()
+    };
+
+    // Results are appended as they finish so an interrupted run can resume, and the file
+    // is rewritten in input order at the end.
+    let mut out = match &options.out {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| GptError::io(format!("Failed to open {}: {}", path.display(), e)))?,
+        ),
+        None => None,
+    };
+    let progress = BatchProgress::new(
+        jobs.len(),
+        summary.skipped,
+        options.concurrency.min(pending.len()),
+    );
+    let mut finished = stream::iter(pending)
+        .map(|index| {
+            let job = &jobs[index];
+            let (progress, ledger, cache) = (&progress, &ledger, cache.as_ref());
+            async move {
+                let slot = progress.start(&job.id);
+                let result =
+                    run_job(client, api_url, job.request.clone(), options, ledger, cache).await;
+                (index, slot, result)
+            }
+        })
+        .buffer_unordered(options.concurrency);
+
+    let mut next = 0;
+    while let Some((index, slot, result)) = finished.next().await {
+        let result = batch_result(&jobs[index], result);
+        if result.error.is_some() {
+            summary.failed += 1;
+        } else {
+            summary.succeeded += 1;
+        }
+        progress.finish(slot, summary.failed);
+        if let (Some(file), Some(path)) = (&mut out, &options.out) {
+            writeln!(file, "{}", serde_json::to_string(&result)?)
+                .map_err(|e| GptError::io(format!("Failed to write {}: {}", path.display(), e)))?;
+        }
+        results[index] = Some(result);
+        if out.is_none() {
+            while let Some(Some(result)) = results.get(next) {
+                print_json(result, OutputFormat::Jsonl)?;
+                next += 1;
+            }
+        }
+    }
+    drop(finished);
+    progress.close().await;
+
+    if let Some(path) = &options.out {
+        let results: Vec<BatchResult> = results.into_iter().flatten().collect();
+        write_results(path, &results)?;
+    }
+    Ok(summary)
+}
+
+pub fn report_summary(summary: BatchSummary, options: &CliOptions) -> Result<(), Box<dyn Error>> {
+    let total = summary.succeeded + summary.failed + summary.skipped;
+
+    match options.output {
+        OutputFormat::Text => eprintln!(
+            "{}",
+            format!(
+                "{} requests: {} succeeded, {} failed, {} skipped",
+                total, summary.succeeded, summary.failed, summary.skipped
+            )
+            .dimmed()
+        ),
+        _ => eprintln!("{}", json!({ "batch": summary })),
+    }
+    if summary.failed > 0 {
+        return Err(GptError::requests_failed(format!(
+            "{} of {} batch requests failed",
+            summary.failed, total
+        ))
+        .into());
+    }
+    Ok(())
+}
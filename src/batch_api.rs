@@ -0,0 +1,446 @@
+use crate::batch::{parse_batch, report_summary, write_results, BatchResult, BatchSummary};
+use crate::chat::ChatCompletionResponse;
+use crate::constants::{
+    BATCH_COMPLETION_WINDOW, BATCH_POLL_INTERVAL_SECS, BATCH_PRICE_DISCOUNT, OPENAI_API_BASE,
+};
+use crate::error::{with_context, GptError};
+use crate::options::CliOptions;
+use crate::output::{print_json, print_warning, OutputFormat};
+use crate::redact;
+use crate::usage::UsageLedger;
+use crate::utils::{
+    build_headers, create_spinner, enforce_budget, post_openai_request, prepare_chat_request,
+    send_openai_request,
+};
+use reqwest::{
+    header::CONTENT_TYPE,
+    multipart::{Form, Part},
+    Client, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+pub const BATCH_SUBMIT: &str = "submit";
+pub const BATCH_STATUS: &str = "status";
+pub const BATCH_FETCH: &str = "fetch";
+pub const BATCH_CANCEL: &str = "cancel";
+
+const CHAT_ENDPOINT: &str = "/v1/chat/completions";
+const TERMINAL_STATUSES: &[&str] = &["completed", "failed", "expired", "cancelled"];
+
+#[derive(Deserialize, Debug)]
+pub struct FileObject {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RequestCounts {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchStatus {
+    pub id: String,
+    pub status: String,
+    pub input_file_id: String,
+    #[serde(default)]
+    pub output_file_id: Option<String>,
+    #[serde(default)]
+    pub error_file_id: Option<String>,
+    #[serde(default)]
+    pub request_counts: Option<RequestCounts>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Value>,
+}
+
+impl BatchStatus {
+    pub fn is_terminal(&self) -> bool {
+        TERMINAL_STATUSES.contains(&self.status.as_str())
+    }
+
+    pub fn describe(&self) -> String {
+        let counts = self.request_counts.clone().unwrap_or_default();
+        format!(
+            "Batch {} is {} ({}/{} completed, {} failed)",
+            self.id, self.status, counts.completed, counts.total, counts.failed
+        )
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchInputLine {
+    custom_id: String,
+    body: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchOutputResponse {
+    status_code: u16,
+    body: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchOutputLine {
+    custom_id: String,
+    response: Option<BatchOutputResponse>,
+    error: Option<Value>,
+}
+
+// Turns a `gpt batch` input file into Batch API request lines, keyed by the batch line ids.
+pub fn batch_request_lines(contents: &str, options: &CliOptions) -> Result<String, Box<dyn Error>> {
+    let mut lines = String::new();
+    for mut job in parse_batch(contents, options)? {
+        prepare_chat_request(&mut job.request, options)
+            .map_err(|e| with_context(e, &format!("Batch request {}", job.id)))?;
+        let mut body = serde_json::to_value(&job.request)?;
+        redact::redact_request(&mut body, options)
+            .map_err(|e| with_context(e, &format!("Batch request {}", job.id)))?;
+        let line = json!({
+            "custom_id": job.id,
+            "method": "POST",
+            "url": CHAT_ENDPOINT,
+            "body": body,
+        });
+        lines.push_str(&line.to_string());
+        lines.push('\n');
+    }
+    if lines.is_empty() {
+        return Err(GptError::invalid_input("Batch input has no requests").into());
+    }
+    Ok(lines)
+}
+
+pub async fn upload_batch_file(
+    client: &Client,
+    base_url: &str,
+    file_name: &str,
+    contents: String,
+    options: &CliOptions,
+) -> Result<FileObject, Box<dyn Error>> {
+    // The multipart form sets its own content type with the boundary.
+    let mut headers = build_headers()?;
+    headers.remove(CONTENT_TYPE);
+    let part = Part::text(contents)
+        .file_name(file_name.to_string())
+        .mime_str("application/jsonl")?;
+    let form = Form::new().text("purpose", "batch").part("file", part);
+    let request = client
+        .post(format!("{}/files", base_url))
+        .headers(headers)
+        .multipart(form);
+    let response = send_openai_request(request, options).await?;
+    Ok(response.json().await?)
+}
+
+pub async fn submit_batch(
+    client: &Client,
+    base_url: &str,
+    input: &Path,
+    options: &CliOptions,
+) -> Result<BatchStatus, Box<dyn Error>> {
+    let contents = fs::read_to_string(input)
+        .map_err(|e| GptError::io(format!("Failed to read {}: {}", input.display(), e)))?;
+    let lines = batch_request_lines(&contents, options)?;
+    let models: Vec<String> = parse_batch(&contents, options)?
+        .into_iter()
+        .map(|job| job.request.model)
+        .collect();
+    enforce_budget(options, &models)?;
+    let file_name = input
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("batch.jsonl");
+    let file = upload_batch_file(client, base_url, file_name, lines, options).await?;
+    let body = json!({
+        "input_file_id": file.id,
+        "endpoint": CHAT_ENDPOINT,
+        "completion_window": BATCH_COMPLETION_WINDOW,
+    });
+    let response =
+        post_openai_request(client, &format!("{}/batches", base_url), &body, options).await?;
+    Ok(response.json().await?)
+}
+
+pub async fn get_batch(
+    client: &Client,
+    base_url: &str,
+    id: &str,
+    options: &CliOptions,
+) -> Result<BatchStatus, Box<dyn Error>> {
+    let request = client
+        .get(format!("{}/batches/{}", base_url, id))
+        .headers(build_headers()?);
+    Ok(send_openai_request(request, options).await?.json().await?)
+}
+
+pub async fn wait_for_batch(
+    client: &Client,
+    base_url: &str,
+    id: &str,
+    interval: Duration,
+    options: &CliOptions,
+) -> Result<BatchStatus, Box<dyn Error>> {
+    let spinner = create_spinner("green", format!("Waiting for batch {}...", id));
+    let result = async {
+        loop {
+            let status = get_batch(client, base_url, id, options).await?;
+            if status.is_terminal() {
+                return Ok(status);
+            }
+            spinner.set_message(status.describe());
+            tokio::time::sleep(interval).await;
+        }
+    }
+    .await;
+    spinner.finish_and_clear();
+    result
+}
+
+pub async fn cancel_batch(
+    client: &Client,
+    base_url: &str,
+    id: &str,
+    options: &CliOptions,
+) -> Result<BatchStatus, Box<dyn Error>> {
+    let url = format!("{}/batches/{}/cancel", base_url, id);
+    let response = post_openai_request(client, &url, &json!({}), options).await?;
+    Ok(response.json().await?)
+}
+
+pub async fn download_file(
+    client: &Client,
+    base_url: &str,
+    file_id: &str,
+    options: &CliOptions,
+) -> Result<String, Box<dyn Error>> {
+    let request = client
+        .get(format!("{}/files/{}/content", base_url, file_id))
+        .headers(build_headers()?);
+    Ok(send_openai_request(request, options).await?.text().await?)
+}
+
+fn output_error(line: BatchOutputLine) -> Value {
+    match (line.response, line.error) {
+        (Some(response), _) if response.status_code != 200 => {
+            let status = StatusCode::from_u16(response.status_code)
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            GptError::from_api_response(status, &response.body.to_string()).to_json()["error"]
+                .take()
+        }
+        (_, Some(error)) => json!({
+            "kind": "api_error",
+            "code": error.get("code"),
+            "message": error.get("message").and_then(Value::as_str).unwrap_or("Request failed"),
+        }),
+        _ => json!({ "kind": "api_error", "message": "Request failed" }),
+    }
+}
+
+// Matches the output and error files back to the uploaded request lines, in input order.
+pub fn merge_batch_output(
+    status: &BatchStatus,
+    input: &str,
+    output: &str,
+    errors: &str,
+    ledger: &UsageLedger,
+) -> Result<Vec<BatchResult>, Box<dyn Error>> {
+    let mut finished: HashMap<String, BatchOutputLine> = output
+        .lines()
+        .chain(errors.lines())
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str::<BatchOutputLine>)
+        .map(|line| line.map(|line| (line.custom_id.clone(), line)))
+        .collect::<Result<_, _>>()
+        .map_err(|e| GptError::invalid_reply(format!("Invalid batch output: {}", e)))?;
+
+    let recorded: HashSet<String> = ledger
+        .entries()?
+        .into_iter()
+        .filter_map(|entry| entry.request_id)
+        .collect();
+    let mut results = Vec::new();
+    for line in input.lines().filter(|line| !line.trim().is_empty()) {
+        let request: BatchInputLine = serde_json::from_str(line)
+            .map_err(|e| GptError::invalid_reply(format!("Invalid batch input file: {}", e)))?;
+        let model = request.body["model"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let mut result = BatchResult {
+            id: request.custom_id.clone(),
+            model: model.clone(),
+            content: None,
+            usage: None,
+            cost: None,
+            error: None,
+        };
+        let completion = match finished.remove(&request.custom_id) {
+            Some(BatchOutputLine {
+                response: Some(response),
+                error: None,
+                ..
+            }) if response.status_code == 200 => {
+                serde_json::from_value::<ChatCompletionResponse>(response.body)
+                    .map_err(|e| json!({ "kind": "api_error", "message": e.to_string() }))
+            }
+            Some(line) => Err(output_error(line)),
+            None => Err(json!({
+                "kind": "api_error",
+                "message": format!("No result for this request (batch {})", status.status),
+            })),
+        };
+        match completion {
+            Ok(completion) => {
+                let usage = completion.usage.unwrap_or_default();
+                let mut entry = ledger.chat_entry(&model, &usage);
+                entry.cost *= BATCH_PRICE_DISCOUNT;
+                let request_id = format!("{}/{}", status.id, request.custom_id);
+                if !recorded.contains(&request_id) {
+                    entry.request_id = Some(request_id);
+                    if let Err(e) = ledger.record(&entry) {
+                        print_warning(&format!("Failed to record usage: {}", e));
+                    }
+                }
+                result.content = completion
+                    .choices
+                    .into_iter()
+                    .next()
+                    .and_then(|choice| choice.message.content);
+                result.usage = Some(usage);
+                result.cost = Some(entry.cost);
+            }
+            Err(error) => result.error = Some(error),
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+pub async fn fetch_batch(
+    client: &Client,
+    base_url: &str,
+    id: &str,
+    interval: Duration,
+    options: &CliOptions,
+) -> Result<Vec<BatchResult>, Box<dyn Error>> {
+    let status = match get_batch(client, base_url, id, options).await? {
+        status if status.is_terminal() => status,
+        _ if options.wait => wait_for_batch(client, base_url, id, interval, options).await?,
+        status => {
+            return Err(GptError::invalid_input(format!(
+                "{}; fetch it once it completes, or pass --wait",
+                status.describe()
+            ))
+            .into())
+        }
+    };
+    if status.output_file_id.is_none() && status.error_file_id.is_none() {
+        let errors = status
+            .errors
+            .as_ref()
+            .map_or_else(String::new, |errors| format!(": {}", errors));
+        return Err(GptError::requests_failed(format!(
+            "Batch {} {} without results{}",
+            id, status.status, errors
+        ))
+        .into());
+    }
+
+    let input = download_file(client, base_url, &status.input_file_id, options).await?;
+    let mut files = Vec::new();
+    for file_id in [&status.output_file_id, &status.error_file_id] {
+        files.push(match file_id {
+            Some(file_id) => download_file(client, base_url, file_id, options).await?,
+            None => String::new(),
+        });
+    }
+    let ledger = UsageLedger::open(options)?;
+    merge_batch_output(&status, &input, &files[0], &files[1], &ledger)
+}
+
+fn print_status(status: &BatchStatus, options: &CliOptions) -> Result<(), Box<dyn Error>> {
+    match options.output {
+        OutputFormat::Text => println!("{}", status.describe()),
+        format => print_json(status, format)?,
+    }
+    Ok(())
+}
+
+pub async fn process_batch_api_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let action = args[2].as_str();
+    // Uploads, polling and downloads don't go through the cassette, so they can be neither
+    // recorded nor replayed.
+    if options.record.is_some() || options.replay.is_some() {
+        return Err(GptError::invalid_input(format!(
+            "--record and --replay are not supported by gpt batch {}; use gpt batch <input.jsonl> to record or replay a batch",
+            action
+        ))
+        .into());
+    }
+    let target = args.get(3).ok_or_else(|| {
+        let target = if action == BATCH_SUBMIT {
+            "<input.jsonl>"
+        } else {
+            "<batch_id>"
+        };
+        GptError::invalid_input(format!("Usage: gpt batch {} {}", action, target))
+    })?;
+    let interval = Duration::from_secs(BATCH_POLL_INTERVAL_SECS);
+
+    match action {
+        BATCH_SUBMIT => {
+            let status = submit_batch(client, OPENAI_API_BASE, Path::new(target), options).await?;
+            print_status(&status, options)?;
+            if options.output == OutputFormat::Text {
+                eprintln!(
+                    "Check on it with `gpt batch status {}` and download results with `gpt batch fetch {}`.",
+                    status.id, status.id
+                );
+            }
+        }
+        BATCH_STATUS => {
+            let status = if options.wait {
+                wait_for_batch(client, OPENAI_API_BASE, target, interval, options).await?
+            } else {
+                get_batch(client, OPENAI_API_BASE, target, options).await?
+            };
+            print_status(&status, options)?;
+        }
+        BATCH_CANCEL => {
+            let status = cancel_batch(client, OPENAI_API_BASE, target, options).await?;
+            print_status(&status, options)?;
+        }
+        _ => {
+            let results = fetch_batch(client, OPENAI_API_BASE, target, interval, options).await?;
+            match &options.out {
+                Some(path) => write_results(path, &results)?,
+                None => {
+                    for result in &results {
+                        print_json(result, OutputFormat::Jsonl)?;
+                    }
+                }
+            }
+            let failed = results.iter().filter(|r| r.error.is_some()).count();
+            let summary = BatchSummary {
+                succeeded: results.len() - failed,
+                failed,
+                skipped: 0,
+            };
+            report_summary(summary, options)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use crate::chat::ChatResult;
+use crate::config::Config;
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::usage::{current_profile, now};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    Off,
+    Hit,
+    Miss,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CachedResponse {
+    pub created: u64,
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<ChatResult>,
+}
+
+impl CachedResponse {
+    pub fn into_response(self) -> Result<reqwest::Response, Box<dyn Error>> {
+        let (content_type, body) = match self.stream {
+            Some(result) => ("text/event-stream", replay_stream(&result)),
+            None => ("application/json", self.body.unwrap_or_default()),
+        };
+        let response = http::Response::builder()
+            .status(200)
+            .header(CONTENT_TYPE, content_type)
+            .body(body)?;
+        Ok(reqwest::Response::from(response))
+    }
+}
+
+// A cached stream replays as a single chunk, followed by usage like a live stream.
+fn replay_stream(result: &ChatResult) -> String {
+    let chunk = json!({
+        "model": result.model,
+        "choices": [{
+            "delta": { "content": result.content },
+            "finish_reason": result.finish_reason,
+        }],
+    });
+    let mut body = format!("data: {}\n\n", chunk);
+    if let Some(usage) = &result.usage {
+        let chunk = json!({ "model": result.model, "choices": [], "usage": usage });
+        body.push_str(&format!("data: {}\n\n", chunk));
+    }
+    body.push_str("data: [DONE]\n\n");
+    body
+}
+
+pub fn cache_key(endpoint: &str, body: &Value) -> String {
+    // stream_options only asks for usage, which cached replies always carry.
+    let mut body = body.clone();
+    if let Some(object) = body.as_object_mut() {
+        object.remove("stream_options");
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+pub struct ResponseCache {
+    pub dir: PathBuf,
+    pub ttl: Duration,
+    pub max_bytes: u64,
+}
+
+impl ResponseCache {
+    pub fn open(options: &CliOptions) -> Result<Option<Self>, Box<dyn Error>> {
+        let Some(dir) = &options.cache_dir else {
+            return Ok(None);
+        };
+        let config = Config::load()?;
+        let enabled = options.cache.unwrap_or_else(|| {
+            config
+                .profile(&current_profile(options))
+                .cache
+                .unwrap_or(false)
+        });
+        if !enabled {
+            return Ok(None);
+        }
+        Ok(Some(ResponseCache {
+            dir: dir.clone(),
+            ttl: Duration::from_secs(config.cache.ttl_secs),
+            max_bytes: config.cache.max_mb * 1024 * 1024,
+        }))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        let path = self.path(key);
+        let cached: CachedResponse = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+        if now().saturating_sub(cached.created) > self.ttl.as_secs() {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        Some(cached)
+    }
+
+    pub fn put(&self, key: &str, response: &CachedResponse) -> Result<(), Box<dyn Error>> {
+        let path = self.path(key);
+        let write = || -> std::io::Result<()> {
+            fs::create_dir_all(&self.dir)?;
+            fs::write(&path, serde_json::to_string(response)?)
+        };
+        write().map_err(|e| GptError::io(format!("Failed to write {}: {}", path.display(), e)))?;
+        self.prune();
+        Ok(())
+    }
+
+    pub fn store_body(&self, key: &str, endpoint: &str, body: &str) -> Result<(), Box<dyn Error>> {
+        self.put(
+            key,
+            &CachedResponse {
+                created: now(),
+                endpoint: endpoint.to_string(),
+                body: Some(body.to_string()),
+                stream: None,
+            },
+        )
+    }
+
+    pub fn store_stream(
+        &self,
+        key: &str,
+        endpoint: &str,
+        result: &ChatResult,
+    ) -> Result<(), Box<dyn Error>> {
+        self.put(
+            key,
+            &CachedResponse {
+                created: now(),
+                endpoint: endpoint.to_string(),
+                body: None,
+                stream: Some(result.clone()),
+            },
+        )
+    }
+
+    // Drops expired entries, then the oldest ones until the cache fits in max_bytes.
+    pub fn prune(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<(PathBuf, SystemTime, u64)> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((entry.path(), metadata.modified().ok()?, metadata.len()))
+            })
+            .collect();
+        files.sort_by_key(|(_, modified, _)| *modified);
+
+        let mut total: u64 = files.iter().map(|(_, _, size)| size).sum();
+        for (path, modified, size) in files {
+            let expired = modified.elapsed().is_ok_and(|age| age > self.ttl);
+            if (expired || total > self.max_bytes) && fs::remove_file(&path).is_ok() {
+                total -= size;
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::error::GptError;
+use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Transport and account headers that must not be replayed or committed to a cassette.
+const SKIPPED_HEADERS: &[&str] = &[
+    "connection",
+    "content-encoding",
+    "content-length",
+    "openai-organization",
+    "set-cookie",
+    "transfer-encoding",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub body: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedChunk {
+    pub delay_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_base64: Option<String>,
+}
+
+impl RecordedChunk {
+    fn new(bytes: &[u8], delay: Duration) -> Self {
+        let delay_ms = delay.as_millis() as u64;
+        match std::str::from_utf8(bytes) {
+            Ok(text) => RecordedChunk {
+                delay_ms,
+                data: Some(text.to_string()),
+                data_base64: None,
+            },
+            Err(_) => RecordedChunk {
+                delay_ms,
+                data: None,
+                data_base64: Some(base64::encode(bytes)),
+            },
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match (&self.data, &self.data_base64) {
+            (Some(text), _) => text.clone().into_bytes(),
+            (None, Some(encoded)) => base64::decode(encoded).unwrap_or_default(),
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<RecordedChunk>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+impl Interaction {
+    fn matches(&self, url: &str, body: &Value) -> bool {
+        self.request.method == "POST"
+            && url_path(&self.request.url) == url_path(url)
+            && self.request.body == *body
+    }
+}
+
+fn url_path(url: &str) -> String {
+    reqwest::Url::parse(url).map_or_else(|_| url.to_string(), |url| url.path().to_string())
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record(Mutex<File>),
+    Replay(Mutex<Vec<Option<Interaction>>>),
+}
+
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+}
+
+impl Cassette {
+    pub fn record(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path).map_err(|e| {
+            GptError::io(format!(
+                "Failed to create cassette {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(Cassette {
+            path: path.to_path_buf(),
+            mode: Mode::Record(Mutex::new(file)),
+        })
+    }
+
+    pub fn replay(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            GptError::io(format!("Failed to read cassette {}: {}", path.display(), e))
+        })?;
+        let interactions = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line).map(Some).map_err(|e| {
+                    GptError::invalid_input(format!(
+                        "Invalid cassette {} at line {}: {}",
+                        path.display(),
+                        index + 1,
+                        e
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Cassette {
+            path: path.to_path_buf(),
+            mode: Mode::Replay(Mutex::new(interactions)),
+        })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    fn append(&self, interaction: &Interaction) -> Result<(), Box<dyn Error>> {
+        let Mode::Record(file) = &self.mode else {
+            return Ok(());
+        };
+        let line = serde_json::to_string(interaction)?;
+        let mut file = file.lock().map_err(|_| "Cassette writer poisoned")?;
+        writeln!(file, "{}", line).map_err(|e| {
+            GptError::io(format!(
+                "Failed to write cassette {}: {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+        Ok(())
+    }
+
+    // Takes the first unused interaction for this request, so retried requests replay in order.
+    pub fn next_response(
+        &self,
+        url: &str,
+        body: &Value,
+    ) -> Result<reqwest::Response, Box<dyn Error>> {
+        let Mode::Replay(interactions) = &self.mode else {
+            return Err("Cassette is not in replay mode".into());
+        };
+        let mut interactions = interactions
+            .lock()
+            .map_err(|_| "Cassette reader poisoned")?;
+        let interaction = interactions
+            .iter_mut()
+            .find(|slot| slot.as_ref().is_some_and(|i| i.matches(url, body)))
+            .and_then(Option::take)
+            .ok_or_else(|| {
+                GptError::invalid_input(format!(
+                    "No recorded response for POST {} in cassette {}",
+                    url_path(url),
+                    self.path.display()
+                ))
+            })?;
+        replay_response(interaction.response)
+    }
+
+    pub async fn capture(
+        self: &Arc<Self>,
+        url: &str,
+        body: &Value,
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, Box<dyn Error>> {
+        let status = response.status().as_u16();
+        let headers: BTreeMap<String, String> = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let streaming = headers
+            .get(CONTENT_TYPE.as_str())
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method: "POST".to_string(),
+                url: url.to_string(),
+                body: body.clone(),
+            },
+            response: RecordedResponse {
+                status,
+                headers,
+                body: None,
+                chunks: Vec::new(),
+            },
+        };
+
+        if !streaming {
+            let text = response.text().await?;
+            let mut interaction = interaction;
+            interaction.response.body = Some(text);
+            self.append(&interaction)?;
+            return replay_response(interaction.response);
+        }
+
+        let mut recorder = ChunkRecorder {
+            cassette: Arc::clone(self),
+            interaction: Some(interaction.clone()),
+            last: Instant::now(),
+        };
+        let stream = response.bytes_stream().map(move |item| {
+            item.map(|bytes| {
+                recorder.push(&bytes);
+                bytes.to_vec()
+            })
+        });
+        build_response(
+            interaction.response.status,
+            &interaction.response.headers,
+            reqwest::Body::wrap_stream(stream),
+        )
+    }
+}
+
+// Writes the interaction once the stream is dropped, so interrupted streams are kept too.
+struct ChunkRecorder {
+    cassette: Arc<Cassette>,
+    interaction: Option<Interaction>,
+    last: Instant,
+}
+
+impl ChunkRecorder {
+    fn push(&mut self, bytes: &[u8]) {
+        if let Some(interaction) = &mut self.interaction {
+            let chunk = RecordedChunk::new(bytes, self.last.elapsed());
+            interaction.response.chunks.push(chunk);
+            self.last = Instant::now();
+        }
+    }
+}
+
+impl Drop for ChunkRecorder {
+    fn drop(&mut self) {
+        if let Some(interaction) = self.interaction.take() {
+            if let Err(e) = self.cassette.append(&interaction) {
+                eprintln!("Failed to record cassette: {}", e);
+            }
+        }
+    }
+}
+
+fn build_response(
+    status: u16,
+    headers: &BTreeMap<String, String>,
+    body: reqwest::Body,
+) -> Result<reqwest::Response, Box<dyn Error>> {
+    let mut builder = http::Response::builder().status(status);
+    for (name, value) in headers {
+        builder = builder.header(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    Ok(reqwest::Response::from(builder.body(body)?))
+}
+
+fn replay_response(response: RecordedResponse) -> Result<reqwest::Response, Box<dyn Error>> {
+    let body = if response.chunks.is_empty() {
+        reqwest::Body::from(response.body.unwrap_or_default())
+    } else {
+        let chunks = stream::iter(response.chunks).then(|chunk| async move {
+            tokio::time::sleep(Duration::from_millis(chunk.delay_ms)).await;
+            Ok::<_, io::Error>(chunk.bytes())
+        });
+        reqwest::Body::wrap_stream(chunks)
+    };
+    build_response(response.status, &response.headers, body)
+}
+
+pub fn open(
+    record: Option<&Path>,
+    replay: Option<&Path>,
+) -> Result<Option<Arc<Cassette>>, Box<dyn Error>> {
+    match (record, replay) {
+        (Some(_), Some(_)) => {
+            Err(GptError::invalid_input("--record and --replay cannot be used together").into())
+        }
+        (Some(path), None) => Ok(Some(Arc::new(Cassette::record(path)?))),
+        (None, Some(path)) => Ok(Some(Arc::new(Cassette::replay(path)?))),
+        (None, None) => Ok(None),
+    }
+}
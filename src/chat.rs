@@ -1,13 +1,17 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Deserialize, Debug)]
 pub struct ChatApiResponse {
+    pub model: Option<String>,
     pub choices: Vec<ChatChoice>,
+    pub usage: Option<ChatUsage>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ChatChoice {
     pub delta: ChatDelta,
+    pub finish_reason: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -15,14 +19,100 @@ pub struct ChatDelta {
     pub content: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ChatUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ChatResult {
+    pub content: String,
+    pub model: Option<String>,
+    pub finish_reason: Option<String>,
+    pub usage: Option<ChatUsage>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ChatCompletionResponse {
+    pub choices: Vec<ChatCompletionChoice>,
+    pub usage: Option<ChatUsage>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ChatCompletionChoice {
+    pub message: ChatCompletionMessage,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ChatCompletionMessage {
+    pub content: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
 pub struct OpenAiChatRequestBody {
     pub model: String,
     pub messages: Vec<ChatMessageRole>,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+// These are set by gpt itself, so batch lines and templates cannot override them through params.
+const RESERVED_PARAMS: &[&str] = &["model", "messages", "stream", "stream_options"];
+
+// Pulls max_tokens out of user-supplied request params so it can be checked against the
+// context window like --max-tokens.
+pub fn split_params(
+    mut params: Map<String, Value>,
+) -> Result<(Option<u32>, Map<String, Value>), String> {
+    if let Some(key) = params
+        .keys()
+        .find(|key| RESERVED_PARAMS.contains(&key.as_str()))
+    {
+        return Err(format!("{} cannot be set in params", key));
+    }
+    let max_tokens = match params.remove("max_tokens") {
+        None => None,
+        Some(value) => Some(
+            value
+                .as_u64()
+                .and_then(|value| u32::try_from(value).ok())
+                .ok_or("max_tokens must be a positive integer")?,
+        ),
+    };
+    Ok((max_tokens, params))
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StreamOptions {
+    pub include_usage: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ResponseFormat {
+    #[serde(rename = "type")]
+    pub format_type: String,
+    pub json_schema: JsonSchemaFormat,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: Value,
+    pub strict: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatMessageRole {
     pub role: String,
     pub content: String,
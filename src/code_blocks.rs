@@ -0,0 +1,122 @@
+use crate::error::GptError;
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CodeBlock {
+    pub index: usize,
+    pub language: String,
+    pub code: String,
+}
+
+// A ``` or ~~~ fence line: its marker, how many times it repeats and the info after it.
+pub fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let trimmed = line.trim_start();
+    let marker = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = trimmed.chars().take_while(|&c| c == marker).count();
+    (length >= 3).then(|| (marker, length, trimmed[length..].trim()))
+}
+
+pub fn extract_code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(char, usize, CodeBlock)> = None;
+
+    for line in text.lines() {
+        match (&mut current, fence(line)) {
+            (None, Some((marker, length, info))) => {
+                let language = info.split_whitespace().next().unwrap_or("").to_string();
+                current = Some((
+                    marker,
+                    length,
+                    CodeBlock {
+                        index: blocks.len() + 1,
+                        language,
+                        code: String::new(),
+                    },
+                ));
+            }
+            (Some((marker, length, _)), Some((closing, closing_length, info)))
+                if closing == *marker && closing_length >= *length && info.is_empty() =>
+            {
+                if let Some((_, _, block)) = current.take() {
+                    blocks.push(block);
+                }
+            }
+            (Some((_, _, block)), _) => {
+                block.code.push_str(line);
+                block.code.push('\n');
+            }
+            (None, None) => {}
+        }
+    }
+
+    if let Some((_, _, block)) = current {
+        blocks.push(block);
+    }
+    blocks
+}
+
+pub fn extension_for(language: &str) -> String {
+    let language = language.to_lowercase();
+    let extension = match language.as_str() {
+        "rust" => "rs",
+        "python" | "python3" => "py",
+        "javascript" | "node" => "js",
+        "typescript" => "ts",
+        "bash" | "shell" | "zsh" | "console" => "sh",
+        "ruby" => "rb",
+        "kotlin" => "kt",
+        "csharp" | "c#" => "cs",
+        "c++" => "cpp",
+        "markdown" => "md",
+        "yml" => "yaml",
+        "golang" => "go",
+        "powershell" => "ps1",
+        "text" | "plaintext" | "" => "txt",
+        other if other.len() <= 10 && other.chars().all(|c| c.is_ascii_alphanumeric()) => other,
+        _ => "txt",
+    };
+    extension.to_string()
+}
+
+pub fn select_code_blocks(
+    blocks: Vec<CodeBlock>,
+    index: Option<usize>,
+) -> Result<Vec<CodeBlock>, Box<dyn Error>> {
+    if blocks.is_empty() {
+        return Err(GptError::invalid_reply("No code blocks found in reply").into());
+    }
+    match index {
+        None => Ok(blocks),
+        Some(index) if index >= 1 && index <= blocks.len() => {
+            Ok(blocks.into_iter().skip(index - 1).take(1).collect())
+        }
+        Some(index) => Err(GptError::invalid_input(format!(
+            "--code-index {} is out of range, the reply contains {} code block(s)",
+            index,
+            blocks.len()
+        ))
+        .into()),
+    }
+}
+
+pub fn save_code_blocks(blocks: &[CodeBlock], dir: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    fs::create_dir_all(dir)
+        .map_err(|e| GptError::io(format!("Failed to create directory {}: {}", dir, e)))?;
+    let mut paths = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let path = Path::new(dir).join(format!(
+            "block_{}.{}",
+            block.index,
+            extension_for(&block.language)
+        ));
+        fs::write(&path, &block.code)
+            .map_err(|e| GptError::io(format!("Failed to write {}: {}", path.display(), e)))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
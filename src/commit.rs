@@ -0,0 +1,155 @@
+use crate::constants::{COMMIT_SYSTEM_PROMPT, DEFAULT_MODEL, GPT_API_URL, MAX_DIFF_FILE_BYTES};
+use crate::error::GptError;
+use crate::git;
+use crate::options::CliOptions;
+use crate::output::{print_json, OutputFormat};
+use crate::utils::{chat_request, complete_chat, create_spinner};
+use colored::Colorize;
+use reqwest::Client;
+use serde_json::json;
+use std::{
+    error::Error,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    process::Command,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommitChoice {
+    Accept,
+    Edit,
+    Regenerate,
+    Quit,
+}
+
+pub fn commit_prompt(repo: &Path, hint: &str) -> Result<String, Box<dyn Error>> {
+    let staged = git::diff(repo, &["--cached"])?;
+    if staged.trim().is_empty() {
+        return Err(GptError::invalid_input(
+            "Nothing is staged; stage your changes with `git add` first",
+        )
+        .into());
+    }
+    let files = git::parse_diff(&staged);
+    let mut prompt = format!(
+        "Write a commit message for this staged diff:\n\n{}",
+        git::filter_diff(&files, MAX_DIFF_FILE_BYTES)
+    );
+    if !hint.trim().is_empty() {
+        prompt.push_str(&format!("\nNotes from the author: {}\n", hint.trim()));
+    }
+    Ok(prompt)
+}
+
+// Models sometimes wrap the message in a code fence despite being told not to.
+pub fn clean_message(reply: &str) -> String {
+    let reply = reply.trim();
+    let Some(fenced) = reply.strip_prefix("```") else {
+        return reply.to_string();
+    };
+    let body = fenced.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end()
+        .strip_suffix("```")
+        .unwrap_or(body)
+        .trim()
+        .to_string()
+}
+
+pub async fn generate_commit_message(
+    client: &Client,
+    api_url: &str,
+    prompt: &str,
+    options: &CliOptions,
+) -> Result<String, Box<dyn Error>> {
+    let model = options.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    let mut request = chat_request(model, COMMIT_SYSTEM_PROMPT, prompt.to_string());
+    let spinner = create_spinner("green", "Writing commit message...".to_string());
+    let reply = complete_chat(client, api_url, &mut request, options).await;
+    spinner.finish_and_clear();
+
+    let message = clean_message(&reply?);
+    if message.is_empty() {
+        return Err(GptError::invalid_reply("The model returned an empty commit message").into());
+    }
+    Ok(message)
+}
+
+pub fn git_commit(repo: &Path, message: &str, edit: bool) -> Result<(), Box<dyn Error>> {
+    let mut file = tempfile::NamedTempFile::new()
+        .map_err(|e| GptError::io(format!("Failed to create a temporary file: {}", e)))?;
+    writeln!(file, "{}", message)
+        .map_err(|e| GptError::io(format!("Failed to write the commit message: {}", e)))?;
+
+    let mut command = Command::new("git");
+    command
+        .current_dir(repo)
+        .arg("commit")
+        .arg("-F")
+        .arg(file.path());
+    if edit {
+        command.arg("--edit");
+    }
+    let status = command
+        .status()
+        .map_err(|e| GptError::io(format!("Failed to run git: {}", e)))?;
+    if !status.success() {
+        return Err(GptError::command_failed(format!("git commit exited with {}", status)).into());
+    }
+    Ok(())
+}
+
+fn ask() -> Result<CommitChoice, Box<dyn Error>> {
+    loop {
+        eprint!("{} ", "[a]ccept, [e]dit, [r]egenerate or [q]uit?".bold());
+        let mut answer = String::new();
+        let read = io::stdin()
+            .lock()
+            .read_line(&mut answer)
+            .map_err(|e| GptError::io(format!("Failed to read stdin: {}", e)))?;
+        if read == 0 {
+            return Ok(CommitChoice::Quit);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "a" | "accept" | "y" | "yes" => return Ok(CommitChoice::Accept),
+            "e" | "edit" => return Ok(CommitChoice::Edit),
+            "r" | "regenerate" => return Ok(CommitChoice::Regenerate),
+            "q" | "quit" | "n" | "no" => return Ok(CommitChoice::Quit),
+            _ => continue,
+        }
+    }
+}
+
+pub async fn process_commit_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let repo = Path::new(".");
+    let prompt = commit_prompt(repo, &args[2..].join(" "))?;
+    let interactive = !options.yes && io::stdin().is_terminal();
+
+    loop {
+        let message = generate_commit_message(client, GPT_API_URL, &prompt, options).await?;
+        if options.yes {
+            return git_commit(repo, &message, false);
+        }
+        if !interactive {
+            // Without a terminal to confirm on, just print the message for scripts to use.
+            return match options.output {
+                OutputFormat::Text => {
+                    println!("{}", message);
+                    Ok(())
+                }
+                format => print_json(&json!({ "message": message }), format),
+            };
+        }
+
+        println!("\n{}\n", message);
+        match ask()? {
+            CommitChoice::Accept => return git_commit(repo, &message, false),
+            CommitChoice::Edit => return git_commit(repo, &message, true),
+            CommitChoice::Regenerate => continue,
+            CommitChoice::Quit => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use crate::chat::{ChatUsage, StreamOptions};
+use crate::constants::{
+    DEFAULT_LOCAL_API_BASE, DEFAULT_SYSTEM_PROMPT, GPT_API_URL, LOCAL_API_BASE_ENV,
+    LOCAL_MODEL_PREFIX,
+};
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::output::{markdown_enabled, print_json, print_warning, render_markdown, OutputFormat};
+use crate::usage::UsageLedger;
+use crate::utils::{
+    build_headers, chat_request, chat_usage, enforce_budget, local_headers, post_request,
+    prepare_chat_request, read_chat_stream,
+};
+use colored::Colorize;
+use console::Term;
+use futures::future::join_all;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    env,
+    error::Error,
+    io::{self, IsTerminal},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const PREVIEW_CHARS: usize = 60;
+const MIN_COLUMN_WIDTH: usize = 30;
+const COLUMN_SEPARATOR: &str = " │ ";
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelTarget {
+    pub label: String,
+    pub model: String,
+    pub api_url: String,
+    pub local: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ModelRun {
+    pub model: String,
+    pub content: String,
+    pub seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_token_seconds: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+    pub cost: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn model_targets(models: &[String], api_url: &str, local_base: &str) -> Vec<ModelTarget> {
+    models
+        .iter()
+        .map(|label| match label.strip_prefix(LOCAL_MODEL_PREFIX) {
+            Some(model) => ModelTarget {
+                label: label.clone(),
+                model: model.to_string(),
+                api_url: format!("{}/chat/completions", local_base.trim_end_matches('/')),
+                local: true,
+            },
+            None => ModelTarget {
+                label: label.clone(),
+                model: label.clone(),
+                api_url: api_url.to_string(),
+                local: false,
+            },
+        })
+        .collect()
+}
+
+// The last line of the reply so far, shortened to fit next to the spinner.
+fn preview(content: &str) -> String {
+    let line = content
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default()
+        .trim();
+    let count = line.chars().count();
+    if count <= PREVIEW_CHARS {
+        return line.to_string();
+    }
+    let tail: String = line.chars().skip(count - PREVIEW_CHARS).collect();
+    format!("…{}", tail)
+}
+
+// The width of each of `count` columns side by side in `total` characters, if they fit.
+pub fn column_width(count: usize, total: usize) -> Option<usize> {
+    let gaps = COLUMN_SEPARATOR.chars().count() * count.saturating_sub(1);
+    let width = total.saturating_sub(gaps) / count.max(1);
+    (width >= MIN_COLUMN_WIDTH).then_some(width)
+}
+
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.trim_end_matches('\n').split('\n') {
+        let chars: Vec<char> = line.replace('\t', "    ").chars().collect();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(
+            chars
+                .chunks(width)
+                .map(|chunk| chunk.iter().collect::<String>()),
+        );
+    }
+    lines
+}
+
+// One row per line: the labels, a rule, then each text wrapped into its column. With `tail`,
+// only the last `tail` lines of each column are kept.
+pub fn side_by_side(
+    labels: &[String],
+    texts: &[String],
+    width: usize,
+    tail: Option<usize>,
+) -> Vec<String> {
+    let columns: Vec<Vec<String>> = texts
+        .iter()
+        .map(|text| {
+            let lines = wrap(text, width);
+            let skip = tail.map_or(0, |tail| lines.len().saturating_sub(tail));
+            lines.into_iter().skip(skip).collect()
+        })
+        .collect();
+    let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+    let row = |cell: &dyn Fn(usize) -> String| -> String {
+        let cells: Vec<String> = (0..columns.len())
+            .map(|index| format!("{:<width$}", cell(index), width = width))
+            .collect();
+        cells.join(COLUMN_SEPARATOR).trim_end().to_string()
+    };
+
+    let mut rows = vec![
+        row(&|index| labels[index].chars().take(width).collect()),
+        row(&|_| "─".repeat(width)),
+    ];
+    for line in 0..height {
+        rows.push(row(&|index| {
+            columns[index].get(line).cloned().unwrap_or_default()
+        }));
+    }
+    rows
+}
+
+// Replies stream into their own column, redrawn in place on stderr.
+struct LiveColumns {
+    term: Term,
+    labels: Vec<String>,
+    texts: Vec<String>,
+    width: usize,
+    height: usize,
+    drawn: usize,
+    last_draw: Option<Instant>,
+}
+
+impl LiveColumns {
+    fn draw(&mut self, force: bool) {
+        if !force
+            && self
+                .last_draw
+                .is_some_and(|last| last.elapsed() < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        let rows = side_by_side(&self.labels, &self.texts, self.width, Some(self.height));
+        let _ = self.term.clear_last_lines(self.drawn);
+        for (index, row) in rows.iter().enumerate() {
+            let row = if index == 0 {
+                row.bold().yellow().to_string()
+            } else {
+                row.clone()
+            };
+            let _ = self.term.write_line(&row);
+        }
+        self.drawn = rows.len();
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+enum Progress {
+    Columns(Mutex<LiveColumns>),
+    Spinners(Vec<ProgressBar>),
+}
+
+impl Progress {
+    fn update(&self, index: usize, content: &str) {
+        match self {
+            Progress::Columns(columns) => {
+                let mut columns = columns.lock().unwrap_or_else(|e| e.into_inner());
+                columns.texts[index] = content.to_string();
+                columns.draw(false);
+            }
+            Progress::Spinners(bars) => bars[index].set_message(preview(content)),
+        }
+    }
+
+    fn finish(&self, index: usize, run: &ModelRun) {
+        let status = format!("{:.2}s", run.seconds);
+        match self {
+            Progress::Columns(columns) => {
+                let mut columns = columns.lock().unwrap_or_else(|e| e.into_inner());
+                columns.labels[index] = format!("{} ({})", run.model, status);
+                if let Some(error) = &run.error {
+                    columns.texts[index] = format!("Error: {}", error);
+                }
+                columns.draw(true);
+            }
+            Progress::Spinners(bars) => bars[index].finish_with_message(status),
+        }
+    }
+
+    fn clear(&self) {
+        match self {
+            Progress::Columns(columns) => {
+                let columns = columns.lock().unwrap_or_else(|e| e.into_inner());
+                let _ = columns.term.clear_last_lines(columns.drawn);
+            }
+            Progress::Spinners(bars) => {
+                for bar in bars {
+                    bar.finish_and_clear();
+                }
+            }
+        }
+    }
+}
+
+async fn stream_model(
+    client: &Client,
+    target: &ModelTarget,
+    prompt: &str,
+    options: &CliOptions,
+    ledger: &UsageLedger,
+    progress: (&Progress, usize),
+    run: &mut ModelRun,
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let mut request = chat_request(&target.model, DEFAULT_SYSTEM_PROMPT, prompt.to_string());
+    request.stream_options = Some(StreamOptions {
+        include_usage: true,
+    });
+    prepare_chat_request(&mut request, options)?;
+
+    let headers = if target.local {
+        local_headers
+    } else {
+        build_headers
+    };
+    let response = post_request(client, &target.api_url, headers, &request, options).await?;
+    let mut content = String::new();
+    let mut first_token = None;
+    let result = read_chat_stream(response, options, None, &mut |delta| {
+        first_token.get_or_insert_with(|| started.elapsed().as_secs_f64());
+        content.push_str(delta);
+        progress.0.update(progress.1, &content);
+        Ok(())
+    })
+    .await?;
+
+    let usage = chat_usage(
+        result.usage,
+        &result.content,
+        &request.model,
+        &request.messages,
+    );
+    // Local models are free and don't belong in the OpenAI usage ledger.
+    if !target.local {
+        let entry = ledger.chat_entry(&request.model, &usage);
+        if let Err(e) = ledger.record(&entry) {
+            print_warning(&format!("Failed to record usage: {}", e));
+        }
+        run.cost = entry.cost;
+    }
+    run.first_token_seconds = first_token;
+    run.usage = Some(usage);
+    run.content = result.content;
+    Ok(())
+}
+
+pub async fn compare_models(
+    client: &Client,
+    targets: &[ModelTarget],
+    prompt: &str,
+    options: &CliOptions,
+) -> Result<Vec<ModelRun>, Box<dyn Error>> {
+    let ledger = UsageLedger::open(options)?;
+
+    let term = Term::stderr();
+    let (rows, columns) = term.size();
+    let live = match column_width(targets.len(), usize::from(columns)) {
+        Some(width) if options.output == OutputFormat::Text && io::stderr().is_terminal() => {
+            Some(LiveColumns {
+                term,
+                labels: targets.iter().map(|t| format!("{} …", t.label)).collect(),
+                texts: vec![String::new(); targets.len()],
+                width,
+                height: usize::from(rows).saturating_sub(4).max(5),
+                drawn: 0,
+                last_draw: None,
+            })
+        }
+        _ => None,
+    };
+
+    let mut join = None;
+    let progress = match live {
+        Some(mut live) => {
+            live.draw(true);
+            Progress::Columns(Mutex::new(live))
+        }
+        None => {
+            let multi = MultiProgress::new();
+            let width = targets.iter().map(|t| t.label.len()).max().unwrap_or(0);
+            let bars = targets
+                .iter()
+                .map(|target| {
+                    let bar = multi.add(ProgressBar::new_spinner());
+                    bar.set_style(
+                        ProgressStyle::default_spinner()
+                            .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏", "✓"])
+                            .template("{spinner:.green} {prefix:.bold} {msg}"),
+                    );
+                    bar.set_prefix(format!("{:<width$}", target.label, width = width));
+                    bar.enable_steady_tick(100);
+                    bar
+                })
+                .collect();
+            // MultiProgress only draws while something is joined on it.
+            join = Some(tokio::task::spawn_blocking(move || multi.join_and_clear()));
+            Progress::Spinners(bars)
+        }
+    };
+
+    let runs = join_all(targets.iter().enumerate().map(|(index, target)| {
+        let ledger = &ledger;
+        let progress = &progress;
+        async move {
+            let started = Instant::now();
+            let mut run = ModelRun {
+                model: target.label.clone(),
+                ..ModelRun::default()
+            };
+            if let Err(e) = stream_model(
+                client,
+                target,
+                prompt,
+                options,
+                ledger,
+                (progress, index),
+                &mut run,
+            )
+            .await
+            {
+                run.error = Some(e.to_string());
+            }
+            run.seconds = started.elapsed().as_secs_f64();
+            progress.finish(index, &run);
+            run
+        }
+    }))
+    .await;
+
+    progress.clear();
+    if let Some(join) = join {
+        let _ = join.await;
+    }
+    Ok(runs)
+}
+
+fn print_runs(runs: &[ModelRun], options: &CliOptions) {
+    let total = usize::from(Term::stdout().size().1);
+    match column_width(runs.len(), total) {
+        Some(width) if io::stdout().is_terminal() => {
+            let labels: Vec<String> = runs.iter().map(|run| run.model.clone()).collect();
+            let texts: Vec<String> = runs
+                .iter()
+                .map(|run| match &run.error {
+                    Some(error) => format!("Error: {}", error),
+                    None => run.content.clone(),
+                })
+                .collect();
+            for (index, row) in side_by_side(&labels, &texts, width, None)
+                .iter()
+                .enumerate()
+            {
+                if index == 0 {
+                    println!("{}", row.bold().yellow());
+                } else {
+                    println!("{}", row);
+                }
+            }
+            println!();
+        }
+        _ => print_sections(runs, options),
+    }
+    print_table(runs);
+}
+
+fn print_sections(runs: &[ModelRun], options: &CliOptions) {
+    for run in runs {
+        println!("{}", format!(" {} ", run.model).bold().yellow());
+        match &run.error {
+            Some(error) => println!("{} {}", "Error:".red().bold(), error),
+            None if markdown_enabled(options) => print!("{}", render_markdown(&run.content)),
+            None => println!("{}", run.content),
+        }
+        println!();
+    }
+}
+
+fn print_table(runs: &[ModelRun]) {
+    let width = runs
+        .iter()
+        .map(|run| run.model.len())
+        .max()
+        .unwrap_or(5)
+        .max(5);
+    println!(
+        "{}",
+        format!(
+            "{:<width$}  {:>8}  {:>11}  {:>7}  {:>10}  {:>10}",
+            "Model",
+            "Time",
+            "First token",
+            "Prompt",
+            "Completion",
+            "Cost",
+            width = width
+        )
+        .bold()
+    );
+    for run in runs {
+        let first_token = run
+            .first_token_seconds
+            .map_or_else(|| "-".to_string(), |s| format!("{:.2}s", s));
+        let (prompt_tokens, completion_tokens) = run.usage.as_ref().map_or_else(
+            || ("-".to_string(), "-".to_string()),
+            |u| (u.prompt_tokens.to_string(), u.completion_tokens.to_string()),
+        );
+        let cost = match (&run.error, run.model.starts_with(LOCAL_MODEL_PREFIX)) {
+            (Some(_), _) => "failed".to_string(),
+            (None, true) => "local".to_string(),
+            (None, false) => format!("${:.6}", run.cost),
+        };
+        println!(
+            "{:<width$}  {:>8}  {:>11}  {:>7}  {:>10}  {:>10}",
+            run.model,
+            format!("{:.2}s", run.seconds),
+            first_token,
+            prompt_tokens,
+            completion_tokens,
+            cost,
+            width = width
+        );
+    }
+}
+
+pub async fn process_compare_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let prompt = args[2..].join(" ");
+    if prompt.trim().is_empty() || options.models.len() < 2 {
+        return Err(GptError::invalid_input(
+            "Usage: gpt compare --models <model>,<model>[,...] <prompt>",
+        )
+        .into());
+    }
+    let local_base =
+        env::var(LOCAL_API_BASE_ENV).unwrap_or_else(|_| DEFAULT_LOCAL_API_BASE.to_string());
+    let targets = model_targets(&options.models, GPT_API_URL, &local_base);
+    let priced: Vec<&str> = targets
+        .iter()
+        .filter(|target| !target.local)
+        .map(|target| target.model.as_str())
+        .collect();
+    enforce_budget(options, &priced)?;
+    let runs = compare_models(client, &targets, &prompt, options).await?;
+
+    match options.output {
+        OutputFormat::Text => print_runs(&runs, options),
+        OutputFormat::Jsonl => {
+            for run in &runs {
+                print_json(run, options.output)?;
+            }
+        }
+        _ => print_json(
+            &json!({ "prompt": prompt, "results": runs }),
+            options.output,
+        )?,
+    }
+
+    let failed = runs.iter().filter(|run| run.error.is_some()).count();
+    if failed > 0 {
+        return Err(GptError::requests_failed(format!(
+            "{} of {} models failed",
+            failed,
+            runs.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,409 @@
+use crate::error::GptError;
+use crate::print_help::FLAGS;
+use std::error::Error;
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+struct Subcommand {
+    name: &'static str,
+    description: &'static str,
+    actions: &'static [&'static str],
+    files: bool,
+}
+
+const fn subcommand(
+    name: &'static str,
+    description: &'static str,
+    actions: &'static [&'static str],
+    files: bool,
+) -> Subcommand {
+    Subcommand {
+        name,
+        description,
+        actions,
+        files,
+    }
+}
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    subcommand("4", "Prompt GPT-4", &[], false),
+    subcommand("v", "Describe an image with GPT-4 Vision", &[], true),
+    subcommand("d", "Generate an image with DALL-E 3", &[], false),
+    subcommand("tokens", "Count the tokens in text or a file", &[], true),
+    subcommand("usage", "Summarize recorded usage and cost", &[], false),
+    subcommand(
+        "commit",
+        "Write a commit message for staged changes",
+        &[],
+        false,
+    ),
+    subcommand("edit", "Edit files as instructed", &[], true),
+    subcommand("compare", "Compare models on one prompt", &[], false),
+    subcommand("eval", "Run a suite of prompt tests", &[], true),
+    subcommand("serve", "Serve a local OpenAI-compatible API", &[], false),
+    subcommand("watch", "Re-run a prompt when files change", &[], true),
+    subcommand("review", "Review the changes on this branch", &[], false),
+    subcommand("sh", "Suggest a shell command", &[], false),
+    subcommand("explain-cmd", "Explain a shell command", &[], false),
+    subcommand("run", "Run a saved prompt template", &[], false),
+    subcommand(
+        "templates",
+        "Manage prompt templates",
+        &["list", "show", "new"],
+        false,
+    ),
+    subcommand(
+        "batch",
+        "Run a JSONL file of prompts",
+        &["submit", "status", "fetch", "cancel"],
+        true,
+    ),
+    subcommand(
+        "completions",
+        "Print a shell completion script",
+        SHELLS,
+        false,
+    ),
+    subcommand(
+        "shell-init",
+        "Print shell keybindings and completions",
+        SHELLS,
+        false,
+    ),
+];
+
+// Flags that only appear as the negated half of a help entry.
+const EXTRA_FLAGS: &[(&str, &str)] = &[("--no-cache", "Skip the response cache.")];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FlagValue {
+    None,
+    Any,
+    File,
+    Dir,
+    Choices(&'static [&'static str]),
+}
+
+struct Flag {
+    name: &'static str,
+    placeholder: &'static str,
+    description: &'static str,
+    value: FlagValue,
+}
+
+fn flags() -> Vec<Flag> {
+    FLAGS
+        .iter()
+        .chain(EXTRA_FLAGS)
+        .map(|(spec, description)| {
+            let mut words = spec.split_whitespace();
+            let name = words.next().unwrap_or(spec);
+            let placeholder = words.clone().next().unwrap_or_default();
+            let value = match (name, words.next()) {
+                (_, None) => FlagValue::None,
+                ("--output", _) => FlagValue::Choices(&["text", "json", "jsonl"]),
+                ("--redact", _) => FlagValue::Choices(&["mask", "block", "warn", "off"]),
+                ("--by", _) => FlagValue::Choices(&["model", "profile", "day"]),
+                (_, Some("<file>")) => FlagValue::File,
+                (_, Some("<dir>")) => FlagValue::Dir,
+                _ => FlagValue::Any,
+            };
+            Flag {
+                name,
+                placeholder: placeholder.trim_matches(|c| c == '<' || c == '>'),
+                description,
+                value,
+            }
+        })
+        .collect()
+}
+
+fn names<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    items.collect::<Vec<_>>().join(" ")
+}
+
+fn bash_script() -> String {
+    let flags = flags();
+    let mut value_cases = String::new();
+    for flag in &flags {
+        if let FlagValue::Choices(choices) = flag.value {
+            value_cases.push_str(&format!(
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+                flag.name,
+                choices.join(" ")
+            ));
+        }
+    }
+    let value_flags = names(
+        flags
+            .iter()
+            .filter(|flag| flag.value != FlagValue::None)
+            .map(|flag| flag.name),
+    )
+    .replace(' ', "|");
+    value_cases.push_str(&format!("        {}) return ;;\n", value_flags));
+
+    let mut command_cases = String::new();
+    for command in SUBCOMMANDS.iter().filter(|c| !c.actions.is_empty()) {
+        command_cases.push_str(&format!(
+            "        {}) ((position == 1)) && COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;\n",
+            command.name,
+            command.actions.join(" ")
+        ));
+    }
+
+    BASH_COMPLETIONS
+        .replace("@VALUE_CASES@", &value_cases)
+        .replace("@VALUE_FLAGS@", &value_flags)
+        .replace("@FLAGS@", &names(flags.iter().map(|flag| flag.name)))
+        .replace("@COMMANDS@", &names(SUBCOMMANDS.iter().map(|c| c.name)))
+        .replace("@COMMAND_CASES@", &command_cases)
+}
+
+fn zsh_quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+fn zsh_script() -> String {
+    let mut commands = String::new();
+    for command in SUBCOMMANDS {
+        commands.push_str(&format!(
+            "        '{}:{}'\n",
+            command.name,
+            zsh_quote(command.description)
+        ));
+    }
+
+    let mut flag_specs = String::new();
+    for flag in flags() {
+        let value = match flag.value {
+            FlagValue::None => String::new(),
+            FlagValue::Any => format!(":{}: ", flag.placeholder),
+            FlagValue::File => format!(":{}:_files", flag.placeholder),
+            FlagValue::Dir => format!(":{}:_files -/", flag.placeholder),
+            FlagValue::Choices(choices) => format!(":{}:({})", flag.placeholder, choices.join(" ")),
+        };
+        flag_specs.push_str(&format!(
+            "        '{}[{}]{}' \\\n",
+            flag.name,
+            zsh_quote(flag.description),
+            value
+        ));
+    }
+
+    let mut command_cases = String::new();
+    for command in SUBCOMMANDS {
+        let completion = match (command.actions.is_empty(), command.files) {
+            (false, false) => format!("compadd -- {}", command.actions.join(" ")),
+            (false, true) => format!(
+                "_alternative 'actions:action:({})' 'files:file:_files'",
+                command.actions.join(" ")
+            ),
+            (true, true) => "_files".to_string(),
+            (true, false) => continue,
+        };
+        command_cases.push_str(&format!(
+            "                {}) (( CURRENT == 2 )) && {} ;;\n",
+            command.name, completion
+        ));
+    }
+
+    ZSH_COMPLETIONS
+        .replace("@COMMANDS@", &commands)
+        .replace("@FLAGS@", &flag_specs)
+        .replace("@COMMAND_CASES@", &command_cases)
+}
+
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish_script() -> String {
+    let mut script = String::from("complete -c gpt -f\n");
+    for command in SUBCOMMANDS {
+        script.push_str(&format!(
+            "complete -c gpt -n __fish_use_subcommand -a {} -d {}\n",
+            command.name,
+            fish_quote(command.description)
+        ));
+    }
+    for command in SUBCOMMANDS.iter().filter(|c| !c.actions.is_empty()) {
+        script.push_str(&format!(
+            "complete -c gpt -n '__fish_seen_subcommand_from {}' -a {}\n",
+            command.name,
+            fish_quote(&command.actions.join(" "))
+        ));
+    }
+    let file_commands = names(SUBCOMMANDS.iter().filter(|c| c.files).map(|c| c.name));
+    script.push_str(&format!(
+        "complete -c gpt -n '__fish_seen_subcommand_from {}' -F\n",
+        file_commands
+    ));
+
+    for flag in flags() {
+        let value = match flag.value {
+            FlagValue::None => String::new(),
+            FlagValue::Any => " -x".to_string(),
+            FlagValue::File | FlagValue::Dir => " -r -F".to_string(),
+            FlagValue::Choices(choices) => format!(" -x -a {}", fish_quote(&choices.join(" "))),
+        };
+        script.push_str(&format!(
+            "complete -c gpt -l {}{} -d {}\n",
+            flag.name.trim_start_matches("--"),
+            value,
+            fish_quote(flag.description)
+        ));
+    }
+    script
+}
+
+fn shell_arg<'a>(args: &'a [String], usage: &str) -> Result<&'a str, GptError> {
+    match args.get(2).map(String::as_str) {
+        Some(shell) if SHELLS.contains(&shell) => Ok(shell),
+        Some(shell) => Err(GptError::invalid_input(format!(
+            "Unsupported shell: {} (expected bash, zsh or fish)",
+            shell
+        ))),
+        None => Err(GptError::invalid_input(usage)),
+    }
+}
+
+pub fn completion_script(shell: &str) -> String {
+    match shell {
+        "zsh" => zsh_script(),
+        "fish" => fish_script(),
+        _ => bash_script(),
+    }
+}
+
+// The keybinding script also sets up completions, so one line in the shell's rc file
+// is enough.
+pub fn shell_init_script(shell: &str) -> String {
+    let keybinding = match shell {
+        "zsh" => ZSH_KEYBINDING,
+        "fish" => FISH_KEYBINDING,
+        _ => BASH_KEYBINDING,
+    };
+    format!("{}\n{}", keybinding, completion_script(shell))
+}
+
+pub fn process_completions_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let shell = shell_arg(args, "Usage: gpt completions bash|zsh|fish")?;
+    print!("{}", completion_script(shell));
+    Ok(())
+}
+
+pub fn process_shell_init_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let shell = shell_arg(args, "Usage: gpt shell-init bash|zsh|fish")?;
+    print!("{}", shell_init_script(shell));
+    Ok(())
+}
+
+const BASH_COMPLETIONS: &str = r#"_gpt() {
+    local cur prev word command="" position=0 skip=0 i
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+
+    case "$prev" in
+@VALUE_CASES@    esac
+
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        word="${COMP_WORDS[i]}"
+        if ((skip)); then
+            skip=0
+            continue
+        fi
+        case "$word" in
+            @VALUE_FLAGS@) skip=1 ;;
+            -*) ;;
+            *)
+                [[ -z "$command" ]] && command="$word"
+                position=$((position + 1))
+                ;;
+        esac
+    done
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "@FLAGS@" -- "$cur"))
+        return
+    fi
+    if ((position == 0)); then
+        COMPREPLY=($(compgen -W "@COMMANDS@" -- "$cur"))
+        return
+    fi
+    case "$command" in
+@COMMAND_CASES@    esac
+}
+complete -o default -F _gpt gpt
+"#;
+
+const ZSH_COMPLETIONS: &str = r#"#compdef gpt
+
+_gpt() {
+    local -a commands
+    commands=(
+@COMMANDS@    )
+    local context state state_descr line
+    typeset -A opt_args
+
+    _arguments \
+@FLAGS@        '1: :->command' \
+        '*:: :->args'
+
+    case $state in
+        command) _describe -t commands 'gpt command' commands ;;
+        args)
+            case $words[1] in
+@COMMAND_CASES@            esac
+            ;;
+    esac
+}
+
+if [[ $zsh_eval_context[-1] == loadautoload ]]; then
+    _gpt "$@"
+else
+    compdef _gpt gpt
+fi
+"#;
+
+// Ctrl-G sends the command line to `gpt sh` and replaces it with the suggested command.
+// With stdin redirected `gpt sh` only prints the command; progress goes to the terminal.
+const BASH_KEYBINDING: &str = r#"_gpt_sh_widget() {
+    [[ -z "$READLINE_LINE" ]] && return
+    local command
+    command=$(gpt sh "$READLINE_LINE" </dev/null 2>/dev/tty)
+    if [[ -n "$command" ]]; then
+        READLINE_LINE="$command"
+        READLINE_POINT=${#command}
+    fi
+}
+bind -x '"\C-g": _gpt_sh_widget'
+"#;
+
+const ZSH_KEYBINDING: &str = r#"_gpt_sh_widget() {
+    [[ -z "$BUFFER" ]] && return
+    local command
+    command=$(gpt sh "$BUFFER" </dev/null 2>/dev/tty)
+    if [[ -n "$command" ]]; then
+        BUFFER="$command"
+        CURSOR=${#BUFFER}
+    fi
+    zle reset-prompt
+}
+zle -N _gpt_sh_widget
+bindkey '^G' _gpt_sh_widget
+"#;
+
+const FISH_KEYBINDING: &str = r#"function _gpt_sh_widget
+    set -l line (commandline)
+    test -z "$line"; and return
+    set -l command (gpt sh "$line" </dev/null 2>/dev/tty | string collect)
+    if test -n "$command"
+        commandline -r -- $command
+    end
+    commandline -f repaint
+end
+bind \cg _gpt_sh_widget
+"#;
@@ -0,0 +1,110 @@
+use crate::constants::{DEFAULT_CACHE_MAX_MB, DEFAULT_CACHE_TTL_SECS};
+use crate::error::GptError;
+use crate::redact::RedactMode;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    error::Error,
+    fs, io,
+    path::PathBuf,
+};
+
+pub const CONFIG_FILE: &str = "config.json";
+pub const TEMPLATES_DIR: &str = "templates";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TokenPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ProfileConfig {
+    pub budget: Option<f64>,
+    pub cache: Option<bool>,
+    pub redact: Option<RedactMode>,
+    pub upstream: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub ttl_secs: u64,
+    pub max_mb: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl_secs: DEFAULT_CACHE_TTL_SECS,
+            max_mb: DEFAULT_CACHE_MAX_MB,
+        }
+    }
+}
+
+// Extra patterns for --redact, by the name shown when they match.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RedactConfig {
+    pub patterns: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub monthly_cap: Option<f64>,
+    pub profiles: HashMap<String, ProfileConfig>,
+    pub prices: HashMap<String, TokenPrice>,
+    pub image_prices: HashMap<String, HashMap<String, f64>>,
+    pub cache: CacheConfig,
+    pub redact: RedactConfig,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let path = config_dir().join(CONFIG_FILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents).map_err(|e| {
+                GptError::invalid_input(format!("Invalid config in {}: {}", path.display(), e))
+                    .into()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(GptError::io(format!("Failed to read {}: {}", path.display(), e)).into()),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(contents)
+    }
+
+    pub fn profile(&self, name: &str) -> ProfileConfig {
+        self.profiles.get(name).cloned().unwrap_or_default()
+    }
+}
+
+pub fn config_dir() -> PathBuf {
+    if let Ok(dir) = env::var("GPT_CONFIG_DIR") {
+        return PathBuf::from(dir);
+    }
+    let base = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|_| PathBuf::from("."));
+    base.join("gpt")
+}
+
+pub fn templates_dir() -> PathBuf {
+    config_dir().join(TEMPLATES_DIR)
+}
+
+pub fn cache_dir() -> PathBuf {
+    if let Ok(dir) = env::var("GPT_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    let base = env::var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|_| PathBuf::from("."));
+    base.join("gpt")
+}
@@ -3,9 +3,14 @@ use crate::images::OpenAiDalleRequestBody;
 use crate::vision::OpenAiVisionRequestBody;
 use serde::Serialize;
 
+pub const OPENAI_API_BASE: &str = "https://api.openai.com/v1";
 pub const GPT_API_URL: &str = "https://api.openai.com/v1/chat/completions";
 pub const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful assistant.";
 pub const GPT4_MODEL: &str = "gpt-4";
+// gpt-3.5-turbo and gpt-4 reject json_schema response formats.
+pub const STRUCTURED_MODEL: &str = "gpt-4o-mini";
+pub const GPT4_STRUCTURED_MODEL: &str = "gpt-4o";
 pub const GPT4_VISION_MODEL: &str = "gpt-4-vision-preview";
 pub const DEFAULT_VISION_INSTRUCTIONS: &str = "What's in the image?";
 pub const DALLE_API_URL: &str = "https://api.openai.com/v1/images/generations";
@@ -13,6 +18,105 @@ pub const DALLE_MODEL: &str = "dall-e-3";
 pub const CMD_VISION: &str = "v";
 pub const CMD_GPT4: &str = "4";
 pub const CMD_DALLE: &str = "d";
+pub const CMD_TOKENS: &str = "tokens";
+pub const CMD_USAGE: &str = "usage";
+pub const CMD_BATCH: &str = "batch";
+pub const CMD_RUN: &str = "run";
+pub const CMD_TEMPLATES: &str = "templates";
+pub const CMD_COMMIT: &str = "commit";
+pub const CMD_REVIEW: &str = "review";
+pub const CMD_SH: &str = "sh";
+pub const CMD_EXPLAIN_CMD: &str = "explain-cmd";
+pub const CMD_COMPLETIONS: &str = "completions";
+pub const CMD_SHELL_INIT: &str = "shell-init";
+pub const CMD_EDIT: &str = "edit";
+pub const CMD_WATCH: &str = "watch";
+pub const CMD_COMPARE: &str = "compare";
+pub const CMD_EVAL: &str = "eval";
+pub const CMD_SERVE: &str = "serve";
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const RETRY_BASE_DELAY_MS: u64 = 500;
+pub const RETRY_MAX_DELAY_MS: u64 = 60_000;
+pub const RETRY_MAX_HINT_MS: u64 = 300_000;
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_FIRST_BYTE_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_TOTAL_TIMEOUT_SECS: u64 = 300;
+pub const MIN_REPLY_TOKENS: usize = 256;
+pub const DEFAULT_CACHE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+pub const DEFAULT_CACHE_MAX_MB: u64 = 100;
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+pub const BATCH_COMPLETION_WINDOW: &str = "24h";
+pub const BATCH_POLL_INTERVAL_SECS: u64 = 30;
+// Requests run through the Batch API are billed at half the synchronous price.
+pub const BATCH_PRICE_DISCOUNT: f64 = 0.5;
+pub const MAX_DIFF_FILE_BYTES: usize = 20_000;
+pub const COMMIT_SYSTEM_PROMPT: &str =
+    "You write git commit messages in the Conventional Commits style. \
+Reply with only the commit message: a subject line of at most 72 characters in the form \
+`type(scope): summary`, then a blank line and a short body explaining what changed and why \
+if the change is not trivial. Do not wrap the message in code fences.";
+
+pub const DEFAULT_REVIEW_BASE: &str = "main";
+// Diff chunks are kept well below the context window so the model has room to reply.
+pub const REVIEW_CHUNK_TOKENS: usize = 6_000;
+pub const REVIEW_REPLY_TOKENS: usize = 2_000;
+pub const REVIEW_SYSTEM_PROMPT: &str = "You are a careful senior engineer reviewing a pull request. \
+Report bugs, security problems, missing error handling and confusing code in the diff you are given. \
+Only comment on added or changed lines. Give the path as shown in the diff and the line number in \
+the new version of the file, counted from the hunk headers. Use severity error for bugs, warning \
+for likely problems and info for suggestions. Reply with an empty findings list if the change \
+looks fine.";
+
+pub const SH_SYSTEM_PROMPT: &str = "You turn requests into a single shell command. \
+Reply with one command line for the user's shell and OS, using pipes or && to combine steps \
+instead of several lines, and a one or two sentence explanation of what it does. Prefer common, \
+preinstalled tools. Mark the command as dangerous if it deletes or overwrites data, changes \
+permissions or system settings, or needs elevated privileges.";
+pub const EXPLAIN_CMD_SYSTEM_PROMPT: &str = "You explain shell commands. \
+Summarize what the whole command does, then break it into its parts (the program, each flag \
+and argument, pipes and redirections) in the order they appear and explain each one briefly. \
+Mention anything that could delete data or is otherwise dangerous in the summary.";
+
+// `local:<model>` in gpt compare talks to an OpenAI-compatible server such as Ollama.
+pub const LOCAL_MODEL_PREFIX: &str = "local:";
+pub const LOCAL_API_BASE_ENV: &str = "GPT_LOCAL_API_BASE";
+pub const LOCAL_API_KEY_ENV: &str = "GPT_LOCAL_API_KEY";
+pub const DEFAULT_LOCAL_API_BASE: &str = "http://localhost:11434/v1";
+
+pub const WATCH_DEBOUNCE_MS: u64 = 300;
+// Only the end of large files is attached, since that is where new log output appears.
+pub const WATCH_MAX_FILE_BYTES: usize = 20_000;
+pub const WATCH_MAX_FILES: usize = 50;
+
+pub const DEFAULT_SERVE_PORT: u16 = 8080;
+
+pub const EVAL_GRADER_SYSTEM_PROMPT: &str = "You grade replies from an AI assistant. \
+You are given a rubric, the prompt the assistant was answering and its reply. Pass the reply only \
+if it meets every point of the rubric, and give a one sentence reason either way.";
+
+pub const BACKUP_EXTENSION: &str = "bak";
+pub const EDIT_SYSTEM_PROMPT: &str = "You edit source files. \
+Reply with one or more search/replace blocks and nothing else. Each block is the file path on \
+its own line, then a line `<<<<<<< SEARCH`, the exact lines to change, a line `=======`, the \
+new lines and a line `>>>>>>> REPLACE`. The SEARCH lines must match the file exactly, including \
+indentation, and include enough context to match only once. Leave SEARCH empty to replace the \
+whole file. Only edit the files you were given.";
+
+pub const TEMPLATE_SCAFFOLD: &str = r#"description = "What this template is for"
+# model = "gpt-4o"
+system = "You are a helpful assistant."
+prompt = """
+{{stdin}}
+"""
+
+# [params]
+# temperature = 0.2
+
+# Default values for {{variables}} used above.
+# [vars]
+# language = "English"
+"#;
 
 #[derive(Serialize)]
 pub enum RequestType {
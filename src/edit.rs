@@ -0,0 +1,471 @@
+use crate::code_blocks::fence;
+use crate::constants::{BACKUP_EXTENSION, DEFAULT_MODEL, EDIT_SYSTEM_PROMPT, GPT_API_URL};
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::output::{print_json, OutputFormat};
+use crate::utils::{chat_request, complete_chat, create_spinner};
+use colored::Colorize;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use similar::TextDiff;
+use std::{
+    error::Error,
+    fs,
+    io::{self, BufRead, IsTerminal},
+    path::{Path, PathBuf},
+};
+
+const SEARCH_MARKER: &str = "<<<<<<< SEARCH";
+const DIVIDER_MARKER: &str = "=======";
+const REPLACE_MARKER: &str = ">>>>>>> REPLACE";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditBlock {
+    pub path: String,
+    pub search: String,
+    pub replace: String,
+    // Where a diff hunk's new lines start, to pick between repeated matches.
+    pub line: Option<usize>,
+    // Other readings of a search/replace block whose text has more than one `=======` line.
+    pub alternatives: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FileChange {
+    pub path: String,
+    #[serde(skip)]
+    pub original: String,
+    #[serde(skip)]
+    pub updated: String,
+    pub diff: String,
+}
+
+fn normalize_path(path: &str) -> &str {
+    path.trim()
+        .trim_matches('`')
+        .trim_end_matches(':')
+        .trim_start_matches("./")
+}
+
+// Replies are either search/replace blocks or a unified diff.
+pub fn parse_edit_blocks(
+    reply: &str,
+    default_path: Option<&str>,
+) -> Result<Vec<EditBlock>, String> {
+    let blocks = if reply.lines().any(|line| line.trim_end() == SEARCH_MARKER) {
+        parse_search_replace(reply, default_path)?
+    } else if reply.lines().any(|line| line.starts_with("@@ -")) {
+        parse_unified_diff(reply, default_path)?
+    } else {
+        Vec::new()
+    };
+    if blocks.is_empty() {
+        return Err("the reply has no search/replace blocks or diff hunks".to_string());
+    }
+    Ok(blocks)
+}
+
+// The path is the last non-blank line before a SEARCH marker, skipping code fences. With a
+// single file every block applies to it, whatever the model wrote above the marker.
+fn parse_search_replace(reply: &str, default_path: Option<&str>) -> Result<Vec<EditBlock>, String> {
+    let mut blocks = Vec::new();
+    let mut path: Option<&str> = None;
+    let mut lines = reply.lines();
+    while let Some(line) = lines.next() {
+        if line.trim_end() != SEARCH_MARKER {
+            if !line.trim().is_empty() && fence(line).is_none() {
+                path = Some(line);
+            }
+            continue;
+        }
+        let path = match (default_path, path.take()) {
+            (Some(default_path), _) => default_path.to_string(),
+            (None, Some(path)) => normalize_path(path).to_string(),
+            (None, None) => return Err("a search/replace block is missing its file path".into()),
+        };
+
+        let mut body = Vec::new();
+        let mut closed = false;
+        for line in lines.by_ref() {
+            if line.trim_end() == REPLACE_MARKER {
+                closed = true;
+                break;
+            }
+            body.push(line);
+        }
+        if !closed {
+            return Err(format!(
+                "the search/replace block for {} is not closed",
+                path
+            ));
+        }
+        // A file line that is itself `=======` shows up as an extra divider. The middle one
+        // is the likeliest split, since unchanged lines appear on both sides; the others are
+        // kept and tried against the file.
+        let dividers: Vec<usize> = (0..body.len())
+            .filter(|&i| body[i].trim_end() == DIVIDER_MARKER)
+            .collect();
+        if dividers.is_empty() {
+            return Err(format!(
+                "the search/replace block for {} has no {} line",
+                path, DIVIDER_MARKER
+            ));
+        }
+        let mut splits: Vec<(String, String)> = dividers
+            .iter()
+            .map(|&i| (join_lines(&body[..i]), join_lines(&body[i + 1..])))
+            .collect();
+        let (search, replace) = splits.remove(dividers.len() / 2);
+        blocks.push(EditBlock {
+            path,
+            search,
+            replace,
+            line: None,
+            alternatives: splits,
+        });
+    }
+    Ok(blocks)
+}
+
+fn diff_path(line: &str) -> &str {
+    let path = line[4..].split('\t').next().unwrap_or_default().trim();
+    path.strip_prefix("b/")
+        .or_else(|| path.strip_prefix("a/"))
+        .unwrap_or(path)
+}
+
+fn hunk_new_start(header: &str) -> Option<usize> {
+    let new = header
+        .split_whitespace()
+        .find(|part| part.starts_with('+'))?;
+    new[1..].split(',').next()?.parse().ok()
+}
+
+// Each hunk becomes a block from its old lines (context and removals) to its new lines
+// (context and additions), applied where the old lines match.
+fn parse_unified_diff(reply: &str, default_path: Option<&str>) -> Result<Vec<EditBlock>, String> {
+    let lines: Vec<&str> = reply.lines().collect();
+    let mut blocks = Vec::new();
+    let mut path: Option<String> = default_path.map(str::to_string);
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if line.starts_with("+++ ") && default_path.is_none() {
+            let new_path = diff_path(line);
+            if new_path == "/dev/null" {
+                return Err("deleting files is not supported".to_string());
+            }
+            path = Some(normalize_path(new_path).to_string());
+            continue;
+        }
+        if !line.starts_with("@@ -") {
+            continue;
+        }
+        let Some(path) = path.clone() else {
+            return Err("a diff hunk is missing its +++ file header".to_string());
+        };
+
+        let mut search = String::new();
+        let mut replace = String::new();
+        let mut last = ' ';
+        let mut blank_lines = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            let file_header = line.starts_with("--- ")
+                && lines
+                    .get(i + 1)
+                    .is_some_and(|next| next.starts_with("+++ "));
+            if line.starts_with("@@") || line.starts_with("diff ") || file_header {
+                break;
+            }
+            // Models often drop the space on blank context lines; a trailing run of blank
+            // lines is just the gap before whatever follows the hunk.
+            if line.is_empty() {
+                blank_lines += 1;
+                i += 1;
+                continue;
+            }
+            let (marker, text) = line.split_at(1);
+            if !matches!(marker, " " | "-" | "+" | "\\") {
+                break;
+            }
+            for _ in 0..std::mem::take(&mut blank_lines) {
+                search.push('\n');
+                replace.push('\n');
+            }
+            match marker {
+                " " => {
+                    search.push_str(&format!("{}\n", text));
+                    replace.push_str(&format!("{}\n", text));
+                }
+                "-" => search.push_str(&format!("{}\n", text)),
+                "+" => replace.push_str(&format!("{}\n", text)),
+                // "\ No newline at end of file" applies to the line before it.
+                _ => {
+                    if last != '+' {
+                        search.pop();
+                    }
+                    if last != '-' {
+                        replace.pop();
+                    }
+                }
+            }
+            last = marker.chars().next().unwrap_or(' ');
+            i += 1;
+        }
+        blocks.push(EditBlock {
+            path,
+            search,
+            replace,
+            line: Some(hunk_new_start(line).unwrap_or(1)),
+            alternatives: Vec::new(),
+        });
+    }
+    Ok(blocks)
+}
+
+fn join_lines(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+fn line_number(contents: &str, offset: usize) -> usize {
+    contents[..offset].matches('\n').count() + 1
+}
+
+fn line_offset(contents: &str, line: usize) -> usize {
+    if line <= 1 {
+        return 0;
+    }
+    contents
+        .match_indices('\n')
+        .nth(line - 2)
+        .map_or(contents.len(), |(i, _)| i + 1)
+}
+
+// Offsets where `search` matches whole lines.
+fn find_matches(contents: &str, search: &str) -> Vec<usize> {
+    contents
+        .match_indices(search)
+        .map(|(i, _)| i)
+        .filter(|&i| i == 0 || contents[..i].ends_with('\n'))
+        .collect()
+}
+
+fn apply_block(contents: &mut String, block: &EditBlock) -> Result<(), String> {
+    // Of the ways to read the block, the longest search text found in the file wins.
+    let (search, replace) = std::iter::once((&block.search, &block.replace))
+        .chain(block.alternatives.iter().map(|(s, r)| (s, r)))
+        .filter(|(search, _)| search.is_empty() || !find_matches(contents, search).is_empty())
+        .max_by_key(|(search, _)| search.len())
+        .unwrap_or((&block.search, &block.replace));
+
+    if search.is_empty() {
+        match block.line {
+            Some(line) => contents.insert_str(line_offset(contents, line), replace),
+            None => *contents = replace.clone(),
+        }
+        return Ok(());
+    }
+    let matches = find_matches(contents, search);
+    let start = match (matches.as_slice(), block.line) {
+        ([], _) => {
+            return Err(format!(
+                "the text to replace was not found in {}:\n{}",
+                block.path, search
+            ))
+        }
+        ([start], _) => *start,
+        (matches, Some(line)) => *matches
+            .iter()
+            .min_by_key(|&&start| line_number(contents, start).abs_diff(line))
+            .unwrap_or(&matches[0]),
+        (_, None) => {
+            return Err(format!(
+                "the text to replace matches more than once in {}:\n{}",
+                block.path, search
+            ))
+        }
+    };
+    contents.replace_range(start..start + search.len(), replace);
+    Ok(())
+}
+
+// Applies the blocks in order to the given (path, contents) pairs. Only files that end up
+// different are returned.
+pub fn apply_edit_blocks(
+    files: &[(String, String)],
+    blocks: &[EditBlock],
+) -> Result<Vec<FileChange>, String> {
+    let mut updated: Vec<String> = files.iter().map(|(_, contents)| contents.clone()).collect();
+    for block in blocks {
+        let index = files
+            .iter()
+            .position(|(path, _)| normalize_path(path) == block.path)
+            .ok_or_else(|| format!("{} is not one of the files being edited", block.path))?;
+        apply_block(&mut updated[index], block)?;
+    }
+
+    Ok(files
+        .iter()
+        .zip(updated)
+        .filter(|((_, original), updated)| original != updated)
+        .map(|((path, original), updated)| FileChange {
+            path: path.clone(),
+            diff: unified_diff(path, original, &updated),
+            original: original.clone(),
+            updated,
+        })
+        .collect())
+}
+
+pub fn unified_diff(path: &str, original: &str, updated: &str) -> String {
+    TextDiff::from_lines(original, updated)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+fn print_diff(diff: &str) {
+    for line in diff.lines() {
+        let colored = if line.starts_with("+++") || line.starts_with("---") {
+            line.bold()
+        } else if line.starts_with('+') {
+            line.green()
+        } else if line.starts_with('-') {
+            line.red()
+        } else if line.starts_with("@@") {
+            line.cyan()
+        } else {
+            line.normal()
+        };
+        println!("{}", colored);
+    }
+}
+
+pub fn edit_prompt(files: &[(String, String)], instruction: &str) -> String {
+    let mut prompt = String::new();
+    for (path, contents) in files {
+        prompt.push_str(&format!("{}\n```\n{}", path, contents));
+        if !contents.ends_with('\n') {
+            prompt.push('\n');
+        }
+        prompt.push_str("```\n\n");
+    }
+    prompt.push_str(&format!("Instruction: {}", instruction));
+    prompt
+}
+
+pub async fn request_edits(
+    client: &Client,
+    api_url: &str,
+    files: &[(String, String)],
+    instruction: &str,
+    options: &CliOptions,
+) -> Result<Vec<FileChange>, Box<dyn Error>> {
+    let model = options.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    let mut request = chat_request(model, EDIT_SYSTEM_PROMPT, edit_prompt(files, instruction));
+    let spinner = create_spinner("green", "Editing...".to_string());
+    let reply = complete_chat(client, api_url, &mut request, options).await;
+    spinner.finish_and_clear();
+
+    let default_path = match files {
+        [(path, _)] => Some(normalize_path(path)),
+        _ => None,
+    };
+    let reply = reply?;
+    parse_edit_blocks(&reply, default_path)
+        .and_then(|blocks| apply_edit_blocks(files, &blocks))
+        .map_err(|e| {
+            GptError::invalid_reply(format!("Could not apply the model's edits: {}", e)).into()
+        })
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".{}", BACKUP_EXTENSION));
+    PathBuf::from(backup)
+}
+
+// Each original is copied to <file>.bak before the new contents are written.
+pub fn write_changes(changes: &[FileChange]) -> Result<(), Box<dyn Error>> {
+    for change in changes {
+        let path = Path::new(&change.path);
+        let backup = backup_path(path);
+        fs::write(&backup, &change.original)
+            .map_err(|e| GptError::io(format!("Failed to write {}: {}", backup.display(), e)))?;
+        fs::write(path, &change.updated)
+            .map_err(|e| GptError::io(format!("Failed to write {}: {}", path.display(), e)))?;
+        eprintln!(
+            "Updated {} (backup in {})",
+            path.display(),
+            backup.display()
+        );
+    }
+    Ok(())
+}
+
+fn confirm() -> Result<bool, Box<dyn Error>> {
+    eprint!("{} ", "Apply these changes? [y/N]".bold());
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| GptError::io(format!("Failed to read stdin: {}", e)))?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub async fn process_edit_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let Some((instruction, paths)) = args.get(2..).and_then(|rest| rest.split_last()) else {
+        return Err(GptError::invalid_input("Usage: gpt edit <file>... \"<instruction>\"").into());
+    };
+    if paths.is_empty() {
+        return Err(GptError::invalid_input("Usage: gpt edit <file>... \"<instruction>\"").into());
+    }
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| GptError::io(format!("Failed to read {}: {}", path, e)))?;
+        files.push((path.clone(), contents));
+    }
+
+    let changes = request_edits(client, GPT_API_URL, &files, instruction, options).await?;
+    let apply = options.yes && !options.dry_run;
+    if options.output != OutputFormat::Text {
+        if apply {
+            write_changes(&changes)?;
+        }
+        return print_json(
+            &json!({ "files": changes, "applied": apply }),
+            options.output,
+        );
+    }
+    if changes.is_empty() {
+        println!("No changes.");
+        return Ok(());
+    }
+    for change in &changes {
+        print_diff(&change.diff);
+    }
+
+    if apply {
+        return write_changes(&changes);
+    }
+    if options.dry_run {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        eprintln!("Not applied; pass --yes to write the changes without confirmation.");
+        return Ok(());
+    }
+    if confirm()? {
+        write_changes(&changes)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,376 @@
+use crate::interrupt::EXIT_INTERRUPTED;
+use crate::retry::StreamInterrupted;
+use crate::timeouts::TimeoutError;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{error::Error, fmt};
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_INVALID_INPUT: i32 = 2;
+pub const EXIT_MISSING_API_KEY: i32 = 3;
+pub const EXIT_AUTH: i32 = 4;
+pub const EXIT_RATE_LIMITED: i32 = 5;
+pub const EXIT_CONTEXT_LENGTH: i32 = 6;
+pub const EXIT_CONTENT_FILTERED: i32 = 7;
+pub const EXIT_NETWORK: i32 = 8;
+pub const EXIT_IO: i32 = 9;
+pub const EXIT_API_ERROR: i32 = 10;
+pub const EXIT_BUDGET_EXCEEDED: i32 = 11;
+pub const EXIT_INVALID_REPLY: i32 = 12;
+pub const EXIT_COMMAND_FAILED: i32 = 13;
+pub const EXIT_REQUESTS_FAILED: i32 = 14;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GptError {
+    MissingApiKey,
+    Auth {
+        message: String,
+    },
+    RateLimited {
+        message: String,
+    },
+    ContextLengthExceeded {
+        message: String,
+    },
+    ContentFiltered {
+        message: String,
+    },
+    Network {
+        message: String,
+    },
+    InvalidInput {
+        message: String,
+    },
+    Io {
+        message: String,
+    },
+    BudgetExceeded {
+        message: String,
+    },
+    // The model replied, but not with something usable (no code blocks, schema mismatch).
+    InvalidReply {
+        message: String,
+    },
+    // A command run on the user's behalf (git, the editor, a suggested command) failed.
+    CommandFailed {
+        message: String,
+    },
+    // Some of a batch, eval or compare run failed; the rest still produced results.
+    RequestsFailed {
+        message: String,
+    },
+    Interrupted,
+    ApiError {
+        status: u16,
+        code: Option<String>,
+        error_type: Option<String>,
+        message: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct OpenAiErrorBody {
+    error: OpenAiErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct OpenAiErrorDetail {
+    message: Option<String>,
+    #[serde(rename = "type")]
+    error_type: Option<String>,
+    code: Option<Value>,
+}
+
+impl GptError {
+    pub fn from_api_response(status: StatusCode, body: &str) -> Self {
+        let detail = serde_json::from_str::<OpenAiErrorBody>(body)
+            .ok()
+            .map(|parsed| parsed.error);
+        let code = detail
+            .as_ref()
+            .and_then(|d| d.code.as_ref())
+            .and_then(|code| match code {
+                Value::String(code) => Some(code.clone()),
+                Value::Null => None,
+                other => Some(other.to_string()),
+            });
+        let error_type = detail.as_ref().and_then(|d| d.error_type.clone());
+        let message = detail
+            .and_then(|d| d.message)
+            .filter(|m| !m.is_empty())
+            .unwrap_or_else(|| match body.trim() {
+                "" => format!("Request failed with status code {}", status),
+                body => body.to_string(),
+            });
+
+        match (status, code.as_deref(), error_type.as_deref()) {
+            (_, Some("context_length_exceeded"), _) => GptError::ContextLengthExceeded { message },
+            (_, Some("content_policy_violation" | "content_filter"), _) => {
+                GptError::ContentFiltered { message }
+            }
+            (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN, _, _) => GptError::Auth { message },
+            (_, Some("invalid_api_key"), _) => GptError::Auth { message },
+            (StatusCode::TOO_MANY_REQUESTS, Some("insufficient_quota"), _) => GptError::ApiError {
+                status: status.as_u16(),
+                code,
+                error_type,
+                message,
+            },
+            (StatusCode::TOO_MANY_REQUESTS, _, _) => GptError::RateLimited { message },
+            _ => GptError::ApiError {
+                status: status.as_u16(),
+                code,
+                error_type,
+                message,
+            },
+        }
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        GptError::InvalidInput {
+            message: message.into(),
+        }
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        GptError::Io {
+            message: message.into(),
+        }
+    }
+
+    pub fn network(message: impl Into<String>) -> Self {
+        GptError::Network {
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_reply(message: impl Into<String>) -> Self {
+        GptError::InvalidReply {
+            message: message.into(),
+        }
+    }
+
+    pub fn command_failed(message: impl Into<String>) -> Self {
+        GptError::CommandFailed {
+            message: message.into(),
+        }
+    }
+
+    pub fn requests_failed(message: impl Into<String>) -> Self {
+        GptError::RequestsFailed {
+            message: message.into(),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GptError::MissingApiKey => "missing_api_key",
+            GptError::Auth { .. } => "auth",
+            GptError::RateLimited { .. } => "rate_limited",
+            GptError::ContextLengthExceeded { .. } => "context_length_exceeded",
+            GptError::ContentFiltered { .. } => "content_filtered",
+            GptError::Network { .. } => "network",
+            GptError::InvalidInput { .. } => "invalid_input",
+            GptError::Io { .. } => "io",
+            GptError::BudgetExceeded { .. } => "budget_exceeded",
+            GptError::InvalidReply { .. } => "invalid_reply",
+            GptError::CommandFailed { .. } => "command_failed",
+            GptError::RequestsFailed { .. } => "requests_failed",
+            GptError::Interrupted => "interrupted",
+            GptError::ApiError { .. } => "api_error",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GptError::MissingApiKey => EXIT_MISSING_API_KEY,
+            GptError::Auth { .. } => EXIT_AUTH,
+            GptError::RateLimited { .. } => EXIT_RATE_LIMITED,
+            GptError::ContextLengthExceeded { .. } => EXIT_CONTEXT_LENGTH,
+            GptError::ContentFiltered { .. } => EXIT_CONTENT_FILTERED,
+            GptError::Network { .. } => EXIT_NETWORK,
+            GptError::InvalidInput { .. } => EXIT_INVALID_INPUT,
+            GptError::Io { .. } => EXIT_IO,
+            GptError::BudgetExceeded { .. } => EXIT_BUDGET_EXCEEDED,
+            GptError::InvalidReply { .. } => EXIT_INVALID_REPLY,
+            GptError::CommandFailed { .. } => EXIT_COMMAND_FAILED,
+            GptError::RequestsFailed { .. } => EXIT_REQUESTS_FAILED,
+            GptError::Interrupted => EXIT_INTERRUPTED,
+            GptError::ApiError { .. } => EXIT_API_ERROR,
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            GptError::MissingApiKey => {
+                Some("Set it with `export OPENAI_API_KEY=your-api-key` or add it to a .env file.")
+            }
+            GptError::Auth { .. } => {
+                Some("Check that OPENAI_API_KEY is valid and has access to the requested model.")
+            }
+            GptError::RateLimited { .. } => {
+                Some("Wait a moment and try again, or raise --max-retries.")
+            }
+            GptError::ContextLengthExceeded { .. } => Some(
+                "Shorten the prompt or conversation, or use a model with a larger context window.",
+            ),
+            GptError::ContentFiltered { .. } => {
+                Some("Rephrase the prompt so it complies with OpenAI's usage policies.")
+            }
+            GptError::Network { .. } => {
+                Some("Check your internet connection or proxy settings and try again.")
+            }
+            GptError::InvalidInput { .. } => Some("Run `gpt -h` for usage."),
+            GptError::BudgetExceeded { .. } => Some(
+                "Check spending with `gpt usage --by profile`, or raise the budget in config.json or with --budget.",
+            ),
+            GptError::InvalidReply { .. } => {
+                Some("Try again, or pick a more capable model with --model.")
+            }
+            GptError::Io { .. }
+            | GptError::CommandFailed { .. }
+            | GptError::RequestsFailed { .. }
+            | GptError::Interrupted => None,
+            GptError::ApiError {
+                code: Some(code), ..
+            } if code == "insufficient_quota" => {
+                Some("Your OpenAI account is out of credits; check your plan and billing details.")
+            }
+            GptError::ApiError { .. } => None,
+        }
+    }
+
+    pub fn with_context(self, context: &str) -> Self {
+        let prefix = |message: String| format!("{}: {}", context, message);
+        match self {
+            GptError::MissingApiKey => GptError::MissingApiKey,
+            GptError::Interrupted => GptError::Interrupted,
+            GptError::Auth { message } => GptError::Auth {
+                message: prefix(message),
+            },
+            GptError::RateLimited { message } => GptError::RateLimited {
+                message: prefix(message),
+            },
+            GptError::ContextLengthExceeded { message } => GptError::ContextLengthExceeded {
+                message: prefix(message),
+            },
+            GptError::ContentFiltered { message } => GptError::ContentFiltered {
+                message: prefix(message),
+            },
+            GptError::Network { message } => GptError::Network {
+                message: prefix(message),
+            },
+            GptError::InvalidInput { message } => GptError::InvalidInput {
+                message: prefix(message),
+            },
+            GptError::Io { message } => GptError::Io {
+                message: prefix(message),
+            },
+            GptError::BudgetExceeded { message } => GptError::BudgetExceeded {
+                message: prefix(message),
+            },
+            GptError::InvalidReply { message } => GptError::InvalidReply {
+                message: prefix(message),
+            },
+            GptError::CommandFailed { message } => GptError::CommandFailed {
+                message: prefix(message),
+            },
+            GptError::RequestsFailed { message } => GptError::RequestsFailed {
+                message: prefix(message),
+            },
+            GptError::ApiError {
+                status,
+                code,
+                error_type,
+                message,
+            } => GptError::ApiError {
+                status,
+                code,
+                error_type,
+                message: prefix(message),
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "kind": self.kind(),
+            "message": self.to_string(),
+            "exit_code": self.exit_code(),
+        });
+        if let Some(hint) = self.hint() {
+            error["hint"] = json!(hint);
+        }
+        if let GptError::ApiError {
+            status,
+            code,
+            error_type,
+            ..
+        } = self
+        {
+            error["status"] = json!(status);
+            error["code"] = json!(code);
+            error["type"] = json!(error_type);
+        }
+        json!({ "error": error })
+    }
+}
+
+impl fmt::Display for GptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GptError::MissingApiKey => {
+                write!(
+                    f,
+                    "OPENAI_API_KEY environment variable not found or invalid"
+                )
+            }
+            GptError::Interrupted => write!(f, "Request interrupted"),
+            GptError::Auth { message }
+            | GptError::RateLimited { message }
+            | GptError::ContextLengthExceeded { message }
+            | GptError::ContentFiltered { message }
+            | GptError::Network { message }
+            | GptError::InvalidInput { message }
+            | GptError::Io { message }
+            | GptError::BudgetExceeded { message }
+            | GptError::InvalidReply { message }
+            | GptError::CommandFailed { message }
+            | GptError::RequestsFailed { message } => write!(f, "{}", message),
+            GptError::ApiError {
+                status, message, ..
+            } => write!(f, "OpenAI API error ({}): {}", status, message),
+        }
+    }
+}
+
+impl Error for GptError {}
+
+pub fn with_context(error: Box<dyn Error>, context: &str) -> Box<dyn Error> {
+    match error.downcast::<GptError>() {
+        Ok(error) => Box::new(error.with_context(context)),
+        Err(error) => format!("{}: {}", context, error).into(),
+    }
+}
+
+pub fn classify(error: &(dyn Error + 'static)) -> Option<GptError> {
+    if let Some(error) = error.downcast_ref::<GptError>() {
+        return Some(error.clone());
+    }
+    if error.is::<TimeoutError>() || error.is::<StreamInterrupted>() {
+        return Some(GptError::network(error.to_string()));
+    }
+    None
+}
+
+pub fn error_json(error: &(dyn Error + 'static)) -> Value {
+    match classify(error) {
+        Some(error) => error.to_json(),
+        None => json!({ "error": { "message": error.to_string(), "exit_code": EXIT_FAILURE } }),
+    }
+}
+
+pub fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    classify(error).map_or(EXIT_FAILURE, |error| error.exit_code())
+}
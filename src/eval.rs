@@ -0,0 +1,566 @@
+use crate::chat::{split_params, ChatMessageRole, OpenAiChatRequestBody};
+use crate::constants::{
+    DEFAULT_MODEL, DEFAULT_SYSTEM_PROMPT, EVAL_GRADER_SYSTEM_PROMPT, GPT_API_URL,
+    LOCAL_MODEL_PREFIX, STRUCTURED_MODEL,
+};
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::output::{print_json, OutputFormat};
+use crate::schema;
+use crate::templates::render;
+use crate::utils::{
+    chat_request, complete_chat, enforce_budget, run_structured_chat, structured_chat_request,
+};
+use colored::Colorize;
+use futures::stream::{self, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fs,
+    path::Path,
+    time::Instant,
+};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EvalSuite {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub grader: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    pub cases: Vec<EvalCase>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EvalCase {
+    pub name: String,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub messages: Option<Vec<ChatMessageRole>>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    // Accepts `- contains: text` rather than serde_yaml's `- !contains text`.
+    #[serde(
+        default,
+        rename = "assert",
+        deserialize_with = "serde_yaml::with::singleton_map_recursive::deserialize"
+    )]
+    pub assertions: Vec<Assertion>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Assertion {
+    Contains(String),
+    Equals(String),
+    Regex(String),
+    JsonSchema(Value),
+    Rubric(String),
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CaseResult {
+    pub case: String,
+    pub model: String,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub seconds: f64,
+}
+
+#[derive(Deserialize)]
+struct Grade {
+    pass: bool,
+    reason: String,
+}
+
+impl Assertion {
+    fn describe(&self) -> String {
+        match self {
+            Assertion::Contains(text) => format!("contains {:?}", text),
+            Assertion::Equals(text) => format!("equals {:?}", text),
+            Assertion::Regex(pattern) => format!("matches /{}/", pattern),
+            Assertion::JsonSchema(_) => "is valid against the JSON schema".to_string(),
+            Assertion::Rubric(rubric) => format!("meets the rubric {:?}", rubric),
+        }
+    }
+}
+
+impl EvalSuite {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let suite: EvalSuite = serde_yaml::from_str(contents).map_err(|e| e.to_string())?;
+        if suite.cases.is_empty() {
+            return Err("the suite has no cases".to_string());
+        }
+        let mut names = HashSet::new();
+        for case in &suite.cases {
+            if !names.insert(case.name.as_str()) {
+                return Err(format!("duplicate case name {}", case.name));
+            }
+            for assertion in &case.assertions {
+                if let Assertion::Regex(pattern) = assertion {
+                    Regex::new(pattern)
+                        .map_err(|e| format!("case {}: invalid regex: {}", case.name, e))?;
+                }
+            }
+        }
+        Ok(suite)
+    }
+
+    // A JSON schema given as a string is a path, relative to the suite file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| GptError::io(format!("Failed to read {}: {}", path.display(), e)))?;
+        let invalid = |e: String| {
+            GptError::invalid_input(format!("Invalid eval suite {}: {}", path.display(), e))
+        };
+        let mut suite = Self::parse(&contents).map_err(invalid)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for case in &mut suite.cases {
+            for assertion in &mut case.assertions {
+                if let Assertion::JsonSchema(Value::String(schema_path)) = assertion {
+                    let schema_path = dir.join(&*schema_path);
+                    let schema = fs::read_to_string(&schema_path)
+                        .map_err(|e| format!("Failed to read {}: {}", schema_path.display(), e))
+                        .and_then(|contents| {
+                            serde_json::from_str(&contents).map_err(|e| {
+                                format!("Invalid JSON schema in {}: {}", schema_path.display(), e)
+                            })
+                        })
+                        .map_err(invalid)?;
+                    *assertion = Assertion::JsonSchema(schema);
+                }
+            }
+        }
+        if suite.name.is_none() {
+            suite.name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned());
+        }
+        Ok(suite)
+    }
+}
+
+fn var_string(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+// Prompts go through the same request building and template rendering as gpt and gpt run.
+pub fn build_case_request(
+    suite: &EvalSuite,
+    case: &EvalCase,
+    model: &str,
+    options: &CliOptions,
+) -> Result<OpenAiChatRequestBody, Box<dyn Error>> {
+    let mut vars: HashMap<String, String> = suite
+        .vars
+        .iter()
+        .chain(&case.vars)
+        .map(|(key, value)| (key.clone(), var_string(value)))
+        .collect();
+    vars.extend(options.vars.iter().cloned());
+    let mut stdin = || -> Result<String, Box<dyn Error>> {
+        Err(GptError::invalid_input("{{stdin}} can't be used in eval suites").into())
+    };
+    let invalid =
+        |message: &str| GptError::invalid_input(format!("Case {}: {}", case.name, message));
+
+    let mut request = match (
+        &case.messages,
+        case.prompt.as_ref().or(suite.prompt.as_ref()),
+    ) {
+        (Some(_), Some(_)) if case.prompt.is_some() => {
+            return Err(invalid("use either prompt or messages, not both").into())
+        }
+        (Some(messages), _) => {
+            let mut request = chat_request(model, DEFAULT_SYSTEM_PROMPT, String::new());
+            request.messages = messages
+                .iter()
+                .map(|message| {
+                    Ok(ChatMessageRole {
+                        role: message.role.clone(),
+                        content: render(&message.content, &vars, &mut stdin)?,
+                    })
+                })
+                .collect::<Result<_, Box<dyn Error>>>()?;
+            request
+        }
+        (None, Some(prompt)) => {
+            let system = match &suite.system {
+                Some(system) => render(system, &vars, &mut stdin)?,
+                None => DEFAULT_SYSTEM_PROMPT.to_string(),
+            };
+            chat_request(model, &system, render(prompt, &vars, &mut stdin)?)
+        }
+        (None, None) => return Err(invalid("expected a prompt or messages").into()),
+    };
+
+    let mut params = suite.params.clone();
+    params.extend(case.params.clone());
+    let (max_tokens, params) = split_params(params).map_err(|e| invalid(&e))?;
+    request.max_tokens = max_tokens;
+    request.params = params;
+    request.stream = false;
+    Ok(request)
+}
+
+// Models often wrap JSON in a code fence even when asked not to.
+fn strip_code_fence(output: &str) -> &str {
+    let trimmed = output.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+pub fn check_output(assertion: &Assertion, output: &str) -> Result<(), String> {
+    let failed = |detail: String| {
+        Err(format!(
+            "expected the reply to {}{}",
+            assertion.describe(),
+            detail
+        ))
+    };
+    match assertion {
+        Assertion::Contains(text) if output.contains(text.as_str()) => Ok(()),
+        Assertion::Equals(text) if output.trim() == text.trim() => Ok(()),
+        Assertion::Regex(pattern) => match Regex::new(pattern) {
+            Ok(regex) if regex.is_match(output) => Ok(()),
+            Ok(_) => failed(String::new()),
+            Err(e) => failed(format!(" ({})", e)),
+        },
+        Assertion::JsonSchema(schema) => {
+            match serde_json::from_str::<Value>(strip_code_fence(output)) {
+                Ok(value) => {
+                    schema::validate(schema, &value).or_else(|e| failed(format!(" ({})", e)))
+                }
+                Err(e) => failed(format!(" (the reply is not valid JSON: {})", e)),
+            }
+        }
+        Assertion::Rubric(_) => Ok(()),
+        _ => failed(String::new()),
+    }
+}
+
+async fn grade(
+    client: &Client,
+    api_url: &str,
+    rubric: &str,
+    request: &OpenAiChatRequestBody,
+    output: &str,
+    grader: &str,
+    options: &CliOptions,
+) -> Result<Grade, Box<dyn Error>> {
+    let prompt = request
+        .messages
+        .iter()
+        .rev()
+        .find(|message| message.role == "user")
+        .map_or("", |message| message.content.as_str());
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "pass": { "type": "boolean" },
+            "reason": { "type": "string" }
+        },
+        "required": ["pass", "reason"],
+        "additionalProperties": false
+    });
+    let request = structured_chat_request(
+        grader,
+        EVAL_GRADER_SYSTEM_PROMPT,
+        format!(
+            "Rubric:\n{}\n\nPrompt:\n{}\n\nReply:\n{}",
+            rubric, prompt, output
+        ),
+        "grade",
+        schema,
+    );
+    let grade = run_structured_chat(client, api_url, request, options).await?;
+    Ok(serde_json::from_value(grade)?)
+}
+
+async fn run_case(
+    client: &Client,
+    api_url: &str,
+    suite: &EvalSuite,
+    case: &EvalCase,
+    model: &str,
+    options: &CliOptions,
+) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let mut request = build_case_request(suite, case, model, options)?;
+    let output = complete_chat(client, api_url, &mut request, options).await?;
+
+    // --model picks the model under test, so it never grades.
+    let grader = suite.grader.as_deref().unwrap_or(STRUCTURED_MODEL);
+    let mut failures = Vec::new();
+    for assertion in &case.assertions {
+        if let Assertion::Rubric(rubric) = assertion {
+            let grade = grade(client, api_url, rubric, &request, &output, grader, options).await?;
+            if !grade.pass {
+                failures.push(format!(
+                    "expected the reply to {}: {}",
+                    assertion.describe(),
+                    grade.reason
+                ));
+            }
+        } else if let Err(failure) = check_output(assertion, &output) {
+            failures.push(failure);
+        }
+    }
+    Ok((output, failures))
+}
+
+pub async fn run_suite(
+    client: &Client,
+    api_url: &str,
+    suite: &EvalSuite,
+    models: &[String],
+    options: &CliOptions,
+) -> Result<Vec<CaseResult>, Box<dyn Error>> {
+    let jobs: Vec<(&EvalCase, &String)> = suite
+        .cases
+        .iter()
+        .flat_map(|case| models.iter().map(move |model| (case, model)))
+        .collect();
+    let progress = ProgressBar::new(jobs.len() as u64);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:30.green/white} {pos}/{len} {msg}")
+            .progress_chars("━━─"),
+    );
+
+    let mut results: Vec<CaseResult> = stream::iter(jobs)
+        .map(|(case, model)| {
+            let progress = &progress;
+            async move {
+                let started = Instant::now();
+                let outcome = run_case(client, api_url, suite, case, model, options).await;
+                let mut result = CaseResult {
+                    case: case.name.clone(),
+                    model: model.clone(),
+                    passed: false,
+                    output: None,
+                    failures: Vec::new(),
+                    error: None,
+                    seconds: started.elapsed().as_secs_f64(),
+                };
+                match outcome {
+                    Ok((output, failures)) => {
+                        result.passed = failures.is_empty();
+                        result.output = Some(output);
+                        result.failures = failures;
+                    }
+                    Err(e) => result.error = Some(e.to_string()),
+                }
+                progress.inc(1);
+                result
+            }
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+    progress.finish_and_clear();
+
+    // Report in suite order rather than completion order.
+    let order = |result: &CaseResult| {
+        let case = suite.cases.iter().position(|c| c.name == result.case);
+        let model = models.iter().position(|m| *m == result.model);
+        (case, model)
+    };
+    results.sort_by_key(order);
+    Ok(results)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than tab and newlines aren't allowed in XML 1.0.
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// One <testsuite> per model, with the suite name as each test's class name.
+pub fn junit_report(name: &str, models: &[String], results: &[CaseResult]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let failures = results.iter().filter(|r| r.error.is_none() && !r.passed);
+    let errors = results.iter().filter(|r| r.error.is_some());
+    xml.push_str(&format!(
+        "<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">\n",
+        escape_xml(name),
+        results.len(),
+        failures.count(),
+        errors.count()
+    ));
+    for model in models {
+        let results: Vec<&CaseResult> = results.iter().filter(|r| r.model == *model).collect();
+        let failures = results.iter().filter(|r| r.error.is_none() && !r.passed);
+        let errors = results.iter().filter(|r| r.error.is_some());
+        let time: f64 = results.iter().map(|r| r.seconds).sum();
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            escape_xml(model),
+            results.len(),
+            failures.count(),
+            errors.count(),
+            time
+        ));
+        for result in results {
+            xml.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape_xml(&result.case),
+                escape_xml(name),
+                result.seconds
+            ));
+            if let Some(error) = &result.error {
+                xml.push_str(&format!(
+                    ">\n      <error message=\"{}\"/>\n    </testcase>\n",
+                    escape_xml(error)
+                ));
+            } else if !result.passed {
+                xml.push_str(&format!(
+                    ">\n      <failure message=\"{}\">{}</failure>\n",
+                    escape_xml(&result.failures[0]),
+                    escape_xml(&result.failures.join("\n"))
+                ));
+                if let Some(output) = &result.output {
+                    xml.push_str(&format!(
+                        "      <system-out>{}</system-out>\n",
+                        escape_xml(output)
+                    ));
+                }
+                xml.push_str("    </testcase>\n");
+            } else {
+                xml.push_str("/>\n");
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn print_results(results: &[CaseResult], models: &[String]) {
+    for result in results {
+        let label = if models.len() > 1 {
+            format!("{} [{}]", result.case, result.model)
+        } else {
+            result.case.clone()
+        };
+        if result.passed {
+            println!("{} {}", "✓".green().bold(), label);
+            continue;
+        }
+        println!("{} {}", "✗".red().bold(), label);
+        if let Some(error) = &result.error {
+            println!("    {} {}", "Error:".red(), error);
+        }
+        for failure in &result.failures {
+            println!("    {}", failure);
+        }
+    }
+}
+
+pub async fn process_eval_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let Some(path) = args.get(2) else {
+        return Err(GptError::invalid_input(
+            "Usage: gpt eval <suite.yaml> [--models <a,b>] [--junit <file>]",
+        )
+        .into());
+    };
+    let suite = EvalSuite::load(Path::new(path))?;
+    let models = match (&options.models, &suite.models, &options.model) {
+        (models, _, _) if !models.is_empty() => models.clone(),
+        (_, models, _) if !models.is_empty() => models.clone(),
+        (_, _, Some(model)) => vec![model.clone()],
+        _ => vec![DEFAULT_MODEL.to_string()],
+    };
+    if let Some(model) = models.iter().find(|m| m.starts_with(LOCAL_MODEL_PREFIX)) {
+        return Err(GptError::invalid_input(format!(
+            "{} can't be evaluated; local models are only supported by gpt compare",
+            model
+        ))
+        .into());
+    }
+    enforce_budget(options, &models)?;
+
+    let results = run_suite(client, GPT_API_URL, &suite, &models, options).await?;
+    let name = suite.name.as_deref().unwrap_or("eval");
+    if let Some(junit) = &options.junit {
+        fs::write(junit, junit_report(name, &models, &results))
+            .map_err(|e| GptError::io(format!("Failed to write {}: {}", junit.display(), e)))?;
+    }
+
+    let passed = results.iter().filter(|r| r.passed).count();
+    let failed = results.len() - passed;
+    match options.output {
+        OutputFormat::Text => {
+            print_results(&results, &models);
+            let summary = format!("{} passed, {} failed", passed, failed);
+            if failed > 0 {
+                println!("\n{}", summary.red().bold());
+            } else {
+                println!("\n{}", summary.green().bold());
+            }
+        }
+        OutputFormat::Jsonl => {
+            for result in &results {
+                print_json(result, options.output)?;
+            }
+        }
+        _ => print_json(
+            &json!({ "suite": name, "passed": passed, "failed": failed, "results": results }),
+            options.output,
+        )?,
+    }
+
+    if failed > 0 {
+        return Err(GptError::requests_failed(format!(
+            "{} of {} eval cases failed",
+            failed,
+            results.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use crate::error::GptError;
+use std::{error::Error, path::Path, process::Command};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDiff {
+    pub path: String,
+    pub text: String,
+    pub binary: bool,
+}
+
+pub fn run_git(repo: &Path, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .map_err(|e| GptError::io(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(GptError::command_failed(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn diff(repo: &Path, range: &[&str]) -> Result<String, Box<dyn Error>> {
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff"];
+    args.extend_from_slice(range);
+    run_git(repo, &args)
+}
+
+pub fn parse_diff(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    for line in diff.split_inclusive('\n') {
+        if let Some(header) = line.strip_prefix("diff --git ") {
+            let path = header
+                .trim_end()
+                .rsplit_once(" b/")
+                .map_or(header.trim_end(), |(_, path)| path);
+            files.push(FileDiff {
+                path: path.to_string(),
+                text: String::new(),
+                binary: false,
+            });
+        }
+        if let Some(file) = files.last_mut() {
+            if line.starts_with("Binary files ") || line.starts_with("GIT binary patch") {
+                file.binary = true;
+            }
+            file.text.push_str(line);
+        }
+    }
+    files
+}
+
+// Binary and oversized file diffs are replaced by a one-line note so the model still
+// knows the file changed.
+pub fn filter_diff(files: &[FileDiff], max_file_bytes: usize) -> String {
+    let mut filtered = String::new();
+    for file in files {
+        if file.binary {
+            filtered.push_str(&format!("Binary file changed: {}\n", file.path));
+        } else if file.text.len() > max_file_bytes {
+            filtered.push_str(&format!(
+                "Large diff omitted ({} bytes): {}\n",
+                file.text.len(),
+                file.path
+            ));
+        } else {
+            filtered.push_str(&file.text);
+        }
+    }
+    filtered
+}
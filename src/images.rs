@@ -9,12 +9,14 @@ pub struct OpenAiDalleRequestBody {
     pub quality: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DalleImageGeneration {
     pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revised_prompt: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DalleApiResponse {
     pub data: Vec<DalleImageGeneration>,
 }
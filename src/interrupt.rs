@@ -0,0 +1,95 @@
+use console::Term;
+use indicatif::{ProgressBar, WeakProgressBar};
+use std::{
+    io::{self, Write},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tokio::sync::Notify;
+
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static CANCEL: Notify = Notify::const_new();
+static SPINNERS: Mutex<Vec<WeakProgressBar>> = Mutex::new(Vec::new());
+
+// The first Ctrl-C while a stream is armed cancels that stream; any other Ctrl-C exits.
+pub fn install() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if !trigger() {
+                let _ = io::stdout().flush();
+                if !clear_spinners() {
+                    eprintln!();
+                }
+                process::exit(EXIT_INTERRUPTED);
+            }
+        }
+    });
+}
+
+pub fn trigger() -> bool {
+    if ARMED.swap(false, Ordering::SeqCst) {
+        CANCELLED.store(true, Ordering::SeqCst);
+        CANCEL.notify_waiters();
+        true
+    } else {
+        false
+    }
+}
+
+// Spinners run before a stream is armed, so a Ctrl-C that exits has to clear them itself.
+pub fn track_spinner(spinner: &ProgressBar) {
+    if let Ok(mut spinners) = SPINNERS.lock() {
+        spinners.retain(|spinner| spinner.upgrade().is_some_and(|s| !s.is_finished()));
+        spinners.push(spinner.downgrade());
+    }
+}
+
+pub fn clear_spinners() -> bool {
+    let spinners = match SPINNERS.lock() {
+        Ok(mut spinners) => std::mem::take(&mut *spinners),
+        Err(_) => return false,
+    };
+    let mut cleared = false;
+    for spinner in spinners.iter().filter_map(WeakProgressBar::upgrade) {
+        if !spinner.is_finished() {
+            spinner.finish_and_clear();
+            cleared = true;
+        }
+    }
+    let _ = Term::stderr().show_cursor();
+    cleared
+}
+
+pub struct CancelGuard;
+
+pub fn arm() -> CancelGuard {
+    CANCELLED.store(false, Ordering::SeqCst);
+    ARMED.store(true, Ordering::SeqCst);
+    CancelGuard
+}
+
+impl CancelGuard {
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = CANCEL.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if CANCELLED.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        ARMED.store(false, Ordering::SeqCst);
+    }
+}
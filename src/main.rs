@@ -1,29 +1,86 @@
+mod batch;
+mod batch_api;
+mod cache;
+mod cassette;
 mod chat;
+mod code_blocks;
+mod commit;
+mod compare;
+mod completions;
+mod config;
 mod constants;
+mod edit;
+mod error;
+mod eval;
+mod git;
 mod images;
+mod interrupt;
+mod markdown;
+mod options;
+mod output;
 mod print_help;
+mod redact;
+mod retry;
+mod review;
+mod schema;
+mod serve;
+mod shell;
+mod templates;
 mod tests;
+mod timeouts;
+mod tokens;
+mod usage;
 mod utils;
 mod vision;
+mod watch;
 
+use crate::error::exit_code;
+use crate::options::{parse_options, CliOptions};
+use crate::output::{print_error, OutputFormat};
 use crate::print_help::print_help;
 use crate::utils::process_command;
-use std::{env, error::Error};
+use std::{env, error::Error, process};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() {
     dotenv::dotenv().ok();
     env_logger::init();
 
     let args: Vec<String> = env::args().collect();
+    let (mut options, args) = match parse_options(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            print_error(e.as_ref(), OutputFormat::Text);
+            process::exit(exit_code(e.as_ref()));
+        }
+    };
+
+    options.usage_ledger = Some(usage::ledger_path());
+    options.cache_dir = Some(config::cache_dir());
+    options.templates_dir = Some(config::templates_dir());
+    options.cassette = match cassette::open(options.record.as_deref(), options.replay.as_deref()) {
+        Ok(cassette) => cassette,
+        Err(e) => {
+            print_error(e.as_ref(), options.output);
+            process::exit(exit_code(e.as_ref()));
+        }
+    };
+    interrupt::install();
+    if let Err(e) = run(&args, &options).await {
+        print_error(e.as_ref(), options.output);
+        process::exit(exit_code(e.as_ref()));
+    }
+}
+
+async fn run(args: &[String], options: &CliOptions) -> Result<(), Box<dyn Error>> {
     if args.len() < 2 || args.iter().any(|arg| arg == "-help" || arg == "-h") {
         print_help();
         return Ok(());
     }
 
     let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
+        .connect_timeout(options.timeouts.connect)
         .build()?;
 
-    process_command(&client, &args).await
+    process_command(&client, args, options).await
 }
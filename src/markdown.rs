@@ -0,0 +1,354 @@
+use crate::code_blocks::fence;
+use colored::Colorize;
+
+struct Language {
+    keywords: &'static str,
+    line_comment: Option<&'static str>,
+    single_quote_strings: bool,
+}
+
+const RUST_KEYWORDS: &str =
+    "as async await break const continue crate dyn else enum extern false fn for if impl in \
+    let loop match mod move mut pub ref return self Self static struct super trait true type \
+    unsafe use where while";
+const PYTHON_KEYWORDS: &str =
+    "and as assert async await break class continue def del elif else except False finally \
+    for from global if import in is lambda None nonlocal not or pass raise return True try \
+    while with yield";
+const JS_KEYWORDS: &str =
+    "async await break case catch class const continue default delete do else export extends \
+    false finally for from function if import in instanceof interface let new null of return \
+    static super switch this throw true try type typeof undefined var void while yield";
+const GO_KEYWORDS: &str =
+    "break case chan const continue default defer else fallthrough false for func go goto if \
+    import interface map nil package range return select struct switch true type var";
+const C_KEYWORDS: &str =
+    "auto bool break case char class const continue default delete do double else enum \
+    extends false final float for if import int long namespace new null nullptr package \
+    private protected public return short signed sizeof static struct switch template this \
+    throw true try typedef union unsigned using virtual void while";
+const SHELL_KEYWORDS: &str =
+    "case do done echo elif else esac exit export fi for function if in local return then \
+    until while";
+const SQL_KEYWORDS: &str =
+    "AND AS BY CREATE DELETE DESC DISTINCT DROP FROM GROUP HAVING INSERT INTO JOIN LEFT LIMIT \
+    NOT NULL ON OR ORDER SELECT SET TABLE UPDATE VALUES WHERE";
+
+fn language(name: &str) -> Language {
+    let (keywords, line_comment, single_quote_strings) = match name.to_lowercase().as_str() {
+        "rust" | "rs" => (RUST_KEYWORDS, Some("//"), false),
+        "python" | "py" => (PYTHON_KEYWORDS, Some("#"), true),
+        "javascript" | "js" | "typescript" | "ts" | "jsx" | "tsx" => {
+            (JS_KEYWORDS, Some("//"), true)
+        }
+        "go" | "golang" => (GO_KEYWORDS, Some("//"), false),
+        "c" | "cpp" | "c++" | "h" | "java" | "kotlin" | "cs" | "csharp" => {
+            (C_KEYWORDS, Some("//"), false)
+        }
+        "sh" | "bash" | "zsh" | "shell" | "console" => (SHELL_KEYWORDS, Some("#"), true),
+        "sql" => (SQL_KEYWORDS, Some("--"), true),
+        "json" => ("", None, false),
+        "toml" | "yaml" | "yml" => ("", Some("#"), true),
+        _ => ("", None, true),
+    };
+    Language {
+        keywords,
+        line_comment,
+        single_quote_strings,
+    }
+}
+
+// The byte index where the run of characters matching `keep` that starts at `from` ends.
+fn scan(line: &str, from: usize, keep: impl Fn(char) -> bool) -> usize {
+    line[from..]
+        .find(|c: char| !keep(c))
+        .map_or(line.len(), |end| from + end)
+}
+
+pub fn highlight_code_line(line: &str, lang: &str) -> String {
+    let language = language(lang);
+    let mut rendered = String::with_capacity(line.len());
+    let mut i = 0;
+
+    while let Some(c) = line[i..].chars().next() {
+        let rest = &line[i..];
+
+        if let Some(comment) = language.line_comment {
+            if rest.starts_with(comment) {
+                rendered.push_str(&rest.dimmed().italic().to_string());
+                break;
+            }
+        }
+
+        if c == '"' || (c == '\'' && language.single_quote_strings) {
+            let mut end = line.len();
+            let mut escaped = false;
+            for (offset, next) in rest.char_indices().skip(1) {
+                if escaped {
+                    escaped = false;
+                } else if next == '\\' {
+                    escaped = true;
+                } else if next == c {
+                    end = i + offset + next.len_utf8();
+                    break;
+                }
+            }
+            rendered.push_str(&line[i..end].green().to_string());
+            i = end;
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let end = scan(line, i, |c| c.is_ascii_alphanumeric() || c == '.');
+            rendered.push_str(&line[i..end].yellow().to_string());
+            i = end;
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let end = scan(line, i, |c| c.is_alphanumeric() || c == '_');
+            let word = &line[i..end];
+            if language.keywords.split_whitespace().any(|k| k == word) {
+                rendered.push_str(&word.magenta().bold().to_string());
+            } else if line[end..].starts_with('(') {
+                rendered.push_str(&word.blue().to_string());
+            } else {
+                rendered.push_str(word);
+            }
+            i = end;
+            continue;
+        }
+
+        rendered.push(c);
+        i += c.len_utf8();
+    }
+
+    rendered
+}
+
+fn find_closing(chars: &[char], from: usize, marker: &[char]) -> Option<usize> {
+    (from..chars.len()).find(|&j| chars[j..].starts_with(marker))
+}
+
+pub fn render_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut rendered = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '`' {
+            if let Some(end) = find_closing(&chars, i + 1, &['`']) {
+                let code: String = chars[i + 1..end].iter().collect();
+                rendered.push_str(&code.cyan().to_string());
+                i = end + 1;
+                continue;
+            }
+        }
+
+        if chars[i..].starts_with(&['*', '*']) || chars[i..].starts_with(&['_', '_']) {
+            let marker = [c, c];
+            if let Some(end) = find_closing(&chars, i + 2, &marker).filter(|&end| end > i + 2) {
+                let inner: String = chars[i + 2..end].iter().collect();
+                rendered.push_str(&render_inline(&inner).bold().to_string());
+                i = end + 2;
+                continue;
+            }
+        }
+
+        if c == '*' && chars.get(i + 1).is_some_and(|next| !next.is_whitespace()) {
+            if let Some(end) = find_closing(&chars, i + 1, &['*']) {
+                let inner: String = chars[i + 1..end].iter().collect();
+                rendered.push_str(&render_inline(&inner).italic().to_string());
+                i = end + 1;
+                continue;
+            }
+        }
+
+        if c == '[' {
+            if let Some(close) = find_closing(&chars, i + 1, &[']', '(']) {
+                if let Some(end) = find_closing(&chars, close + 2, &[')']) {
+                    let label: String = chars[i + 1..close].iter().collect();
+                    let url: String = chars[close + 2..end].iter().collect();
+                    rendered.push_str(&label.underline().to_string());
+                    rendered.push_str(&format!(" ({})", url).dimmed().to_string());
+                    i = end + 1;
+                    continue;
+                }
+            }
+        }
+
+        rendered.push(c);
+        i += 1;
+    }
+
+    rendered
+}
+
+fn is_table_row(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.starts_with('|') && trimmed.len() > 1
+}
+
+fn split_table_row(line: &str) -> Vec<String> {
+    let trimmed = line.trim().trim_start_matches('|').trim_end_matches('|');
+    trimmed
+        .split('|')
+        .map(|cell| cell.trim().to_string())
+        .collect()
+}
+
+fn is_table_separator(cells: &[String]) -> bool {
+    cells
+        .iter()
+        .all(|cell| !cell.is_empty() && cell.chars().all(|c| c == '-' || c == ':' || c == ' '))
+}
+
+fn render_table(rows: &[String]) -> String {
+    let rows: Vec<Vec<String>> = rows.iter().map(|row| split_table_row(row)).collect();
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in rows.iter().filter(|row| !is_table_separator(row)) {
+        for (index, cell) in row.iter().enumerate() {
+            widths[index] = widths[index].max(cell.chars().count());
+        }
+    }
+
+    let mut rendered = String::new();
+    for (row_index, row) in rows.iter().enumerate() {
+        if is_table_separator(row) {
+            let rule: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+            rendered.push_str(&rule.join("┼").dimmed().to_string());
+            rendered.push('\n');
+            continue;
+        }
+        let cells: Vec<String> = (0..columns)
+            .map(|index| {
+                let cell = row.get(index).map_or("", String::as_str);
+                let padding = " ".repeat(widths[index] - cell.chars().count());
+                let cell = if row_index == 0 {
+                    cell.bold().to_string()
+                } else {
+                    render_inline(cell)
+                };
+                format!(" {}{} ", cell, padding)
+            })
+            .collect();
+        rendered.push_str(&cells.join(&"│".dimmed().to_string()));
+        rendered.push('\n');
+    }
+    rendered
+}
+
+#[derive(Default)]
+pub struct MarkdownRenderer {
+    pending: String,
+    // The open code fence's marker, its length and the block's language.
+    code: Option<(char, usize, String)>,
+    table: Vec<String>,
+}
+
+impl MarkdownRenderer {
+    pub fn new() -> Self {
+        MarkdownRenderer::default()
+    }
+
+    pub fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        let mut rendered = String::new();
+        while let Some(end) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=end).collect();
+            rendered.push_str(&self.render_line(line.trim_end_matches(['\n', '\r'])));
+        }
+        rendered
+    }
+
+    pub fn finish(&mut self) -> String {
+        let mut rendered = String::new();
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            rendered.push_str(&self.render_line(&line));
+        }
+        rendered.push_str(&self.flush_table());
+        self.code = None;
+        rendered
+    }
+
+    fn flush_table(&mut self) -> String {
+        if self.table.is_empty() {
+            return String::new();
+        }
+        let rendered = render_table(&self.table);
+        self.table.clear();
+        rendered
+    }
+
+    fn render_line(&mut self, line: &str) -> String {
+        let trimmed = line.trim_start();
+
+        if let Some((marker, length, lang)) = &self.code {
+            if let Some((closing, closing_length, info)) = fence(line) {
+                if closing == *marker && closing_length >= *length && info.is_empty() {
+                    self.code = None;
+                    return format!("{}\n", "└────".dimmed());
+                }
+            }
+            return format!("{} {}\n", "│".dimmed(), highlight_code_line(line, lang));
+        }
+
+        if is_table_row(line) {
+            self.table.push(line.to_string());
+            return String::new();
+        }
+        let mut rendered = self.flush_table();
+
+        if let Some((marker, length, info)) = fence(line) {
+            let lang = info.split_whitespace().next().unwrap_or("").to_string();
+            rendered.push_str(&format!("{} {}\n", "┌────".dimmed(), lang.dimmed()));
+            self.code = Some((marker, length, lang));
+            return rendered;
+        }
+
+        let heading_level = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&heading_level) && trimmed[heading_level..].starts_with(' ') {
+            let title = trimmed[heading_level..].trim();
+            let title = if heading_level == 1 {
+                title.bold().underline().yellow()
+            } else {
+                title.bold().yellow()
+            };
+            rendered.push_str(&format!("{}\n", title));
+            return rendered;
+        }
+
+        if trimmed.len() >= 3
+            && (trimmed.chars().all(|c| c == '-')
+                || trimmed.chars().all(|c| c == '*')
+                || trimmed.chars().all(|c| c == '_'))
+        {
+            rendered.push_str(&format!("{}\n", "─".repeat(40).dimmed()));
+            return rendered;
+        }
+
+        let indent = &line[..line.len() - trimmed.len()];
+        if let Some(quote) = trimmed.strip_prefix("> ") {
+            let quote = render_inline(quote).italic();
+            rendered.push_str(&format!("{}{} {}\n", indent, "│".dimmed(), quote));
+        } else if let Some(item) = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "))
+            .or_else(|| trimmed.strip_prefix("+ "))
+        {
+            rendered.push_str(&format!(
+                "{}{} {}\n",
+                indent,
+                "•".cyan(),
+                render_inline(item)
+            ));
+        } else {
+            rendered.push_str(&format!("{}\n", render_inline(line)));
+        }
+        rendered
+    }
+}
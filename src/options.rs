@@ -0,0 +1,256 @@
+use crate::cassette::Cassette;
+use crate::constants::{DEFAULT_BATCH_CONCURRENCY, DEFAULT_MAX_RETRIES};
+use crate::error::GptError;
+use crate::output::OutputFormat;
+use crate::redact::RedactMode;
+use crate::templates::parse_var;
+use crate::timeouts::Timeouts;
+use crate::usage::{parse_period, UsageGroup};
+use std::{error::Error, path::PathBuf, sync::Arc, time::Duration};
+
+#[derive(Debug, Clone)]
+pub struct CliOptions {
+    pub json_schema: Option<String>,
+    pub schema_retry: bool,
+    pub output: OutputFormat,
+    pub raw: bool,
+    pub code: bool,
+    pub code_index: Option<usize>,
+    pub save_code: Option<String>,
+    pub max_retries: u32,
+    pub timeouts: Timeouts,
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub profile: Option<String>,
+    pub budget: Option<f64>,
+    pub since: Option<Duration>,
+    pub group_by: UsageGroup,
+    pub usage_ledger: Option<PathBuf>,
+    pub cache: Option<bool>,
+    pub cache_dir: Option<PathBuf>,
+    pub stats: bool,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub cassette: Option<Arc<Cassette>>,
+    pub concurrency: usize,
+    pub out: Option<PathBuf>,
+    pub wait: bool,
+    pub vars: Vec<(String, String)>,
+    pub templates_dir: Option<PathBuf>,
+    pub yes: bool,
+    pub base: Option<String>,
+    pub sarif: bool,
+    pub dry_run: bool,
+    pub template: Option<String>,
+    pub prompt: Option<String>,
+    pub models: Vec<String>,
+    pub junit: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub upstream: Option<String>,
+    pub redact: Option<RedactMode>,
+}
+
+impl Default for CliOptions {
+    fn default() -> Self {
+        CliOptions {
+            json_schema: None,
+            schema_retry: true,
+            output: OutputFormat::Text,
+            raw: false,
+            code: false,
+            code_index: None,
+            save_code: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            timeouts: Timeouts::default(),
+            model: None,
+            max_tokens: None,
+            profile: None,
+            budget: None,
+            since: None,
+            group_by: UsageGroup::Model,
+            usage_ledger: None,
+            cache: None,
+            cache_dir: None,
+            stats: false,
+            record: None,
+            replay: None,
+            cassette: None,
+            concurrency: DEFAULT_BATCH_CONCURRENCY,
+            out: None,
+            wait: false,
+            vars: Vec::new(),
+            templates_dir: None,
+            yes: false,
+            base: None,
+            sarif: false,
+            dry_run: false,
+            template: None,
+            prompt: None,
+            models: Vec::new(),
+            junit: None,
+            port: None,
+            upstream: None,
+            redact: None,
+        }
+    }
+}
+
+impl CliOptions {
+    pub fn code_only(&self) -> bool {
+        self.code || self.code_index.is_some()
+    }
+
+    pub fn wants_code(&self) -> bool {
+        self.code_only() || self.save_code.is_some()
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: String, flag: &str) -> Result<T, Box<dyn Error>> {
+    value.parse().map_err(|_| {
+        GptError::invalid_input(format!("Invalid value for {}: {}", flag, value)).into()
+    })
+}
+
+fn parse_seconds(value: String, flag: &str) -> Result<Duration, Box<dyn Error>> {
+    let seconds: f64 = parse_number(value.clone(), flag)?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(
+            GptError::invalid_input(format!("Invalid value for {}: {}", flag, value)).into(),
+        );
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn option_value(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<String, Box<dyn Error>> {
+    args.next()
+        .ok_or_else(|| GptError::invalid_input(format!("Missing value for {}", flag)).into())
+}
+
+pub fn parse_options(args: &[String]) -> Result<(CliOptions, Vec<String>), Box<dyn Error>> {
+    let mut options = CliOptions::default();
+    let mut positional = Vec::with_capacity(args.len());
+    let mut iter = args.iter().cloned();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--json-schema" => options.json_schema = Some(option_value(&mut iter, &arg)?),
+            "--no-schema-retry" => options.schema_retry = false,
+            "--raw" => options.raw = true,
+            "--cache" => options.cache = Some(true),
+            "--no-cache" => options.cache = Some(false),
+            "--stats" => options.stats = true,
+            "--record" => options.record = Some(option_value(&mut iter, &arg)?.into()),
+            "--replay" => options.replay = Some(option_value(&mut iter, &arg)?.into()),
+            "--code" => options.code = true,
+            "--code-index" => {
+                options.code_index = Some(parse_number(option_value(&mut iter, &arg)?, &arg)?)
+            }
+            "--save-code" => options.save_code = Some(option_value(&mut iter, &arg)?),
+            "--max-retries" => {
+                options.max_retries = parse_number(option_value(&mut iter, &arg)?, &arg)?
+            }
+            "--connect-timeout" => {
+                options.timeouts.connect = parse_seconds(option_value(&mut iter, &arg)?, &arg)?
+            }
+            "--first-byte-timeout" => {
+                options.timeouts.first_byte = parse_seconds(option_value(&mut iter, &arg)?, &arg)?
+            }
+            "--idle-timeout" => {
+                options.timeouts.idle = parse_seconds(option_value(&mut iter, &arg)?, &arg)?
+            }
+            "--total-timeout" => {
+                options.timeouts.total = parse_seconds(option_value(&mut iter, &arg)?, &arg)?
+            }
+            "--concurrency" => {
+                let value = option_value(&mut iter, &arg)?;
+                options.concurrency = parse_number(value.clone(), &arg)?;
+                if options.concurrency == 0 {
+                    return Err(GptError::invalid_input(format!(
+                        "Invalid value for {}: {}",
+                        arg, value
+                    ))
+                    .into());
+                }
+            }
+            "--var" => options
+                .vars
+                .push(parse_var(&option_value(&mut iter, &arg)?)?),
+            "--yes" => options.yes = true,
+            "--dry-run" => options.dry_run = true,
+            "--template" => options.template = Some(option_value(&mut iter, &arg)?),
+            "--prompt" => options.prompt = Some(option_value(&mut iter, &arg)?),
+            "--wait" => options.wait = true,
+            "--base" => options.base = Some(option_value(&mut iter, &arg)?),
+            "--sarif" => options.sarif = true,
+            "--out" => options.out = Some(option_value(&mut iter, &arg)?.into()),
+            "--port" => options.port = Some(parse_number(option_value(&mut iter, &arg)?, &arg)?),
+            "--upstream" => options.upstream = Some(option_value(&mut iter, &arg)?),
+            "--junit" => options.junit = Some(option_value(&mut iter, &arg)?.into()),
+            "--models" => {
+                let value = option_value(&mut iter, &arg)?;
+                options.models = value.split(',').map(|m| m.trim().to_string()).collect();
+                if options.models.iter().any(String::is_empty) {
+                    return Err(GptError::invalid_input(format!(
+                        "Invalid value for {}: {}",
+                        arg, value
+                    ))
+                    .into());
+                }
+            }
+            "--model" => options.model = Some(option_value(&mut iter, &arg)?),
+            "--max-tokens" => {
+                options.max_tokens = Some(parse_number(option_value(&mut iter, &arg)?, &arg)?)
+            }
+            "--profile" => options.profile = Some(option_value(&mut iter, &arg)?),
+            "--budget" => {
+                let value = option_value(&mut iter, &arg)?;
+                let budget: f64 = parse_number(value.clone(), &arg)?;
+                if !budget.is_finite() || budget < 0.0 {
+                    return Err(GptError::invalid_input(format!(
+                        "Invalid value for {}: {}",
+                        arg, value
+                    ))
+                    .into());
+                }
+                options.budget = Some(budget);
+            }
+            "--since" => {
+                let value = option_value(&mut iter, &arg)?;
+                options.since = Some(parse_period(&value).ok_or_else(|| {
+                    GptError::invalid_input(format!(
+                        "Invalid value for {}: {} (expected e.g. 12h, 7d or 4w)",
+                        arg, value
+                    ))
+                })?);
+            }
+            "--redact" => {
+                options.redact = Some(
+                    option_value(&mut iter, &arg)?
+                        .parse()
+                        .map_err(GptError::invalid_input)?,
+                )
+            }
+            "--by" => {
+                options.group_by = option_value(&mut iter, &arg)?
+                    .parse()
+                    .map_err(GptError::invalid_input)?
+            }
+            "--output" => {
+                options.output = option_value(&mut iter, &arg)?
+                    .parse()
+                    .map_err(GptError::invalid_input)?
+            }
+            // Everything after -- is prompt text, even if it looks like a flag.
+            "--" => {
+                positional.extend(iter.by_ref());
+                break;
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    Ok((options, positional))
+}
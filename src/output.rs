@@ -0,0 +1,200 @@
+use crate::cache::CacheStatus;
+use crate::chat::ChatResult;
+use crate::error::{classify, error_json, GptError};
+use crate::markdown::MarkdownRenderer;
+use crate::options::CliOptions;
+use crate::usage::UsageEntry;
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    error::Error,
+    io::{self, IsTerminal, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Jsonl,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => Err(format!(
+                "Invalid output format: {} (expected text, json or jsonl)",
+                value
+            )),
+        }
+    }
+}
+
+pub fn to_json_string<T: Serialize>(
+    value: &T,
+    format: OutputFormat,
+) -> Result<String, serde_json::Error> {
+    match format {
+        OutputFormat::Json => serde_json::to_string_pretty(value),
+        _ => serde_json::to_string(value),
+    }
+}
+
+pub fn print_json<T: Serialize>(value: &T, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    println!("{}", to_json_string(value, format)?);
+    Ok(())
+}
+
+pub fn print_error(error: &(dyn Error + 'static), format: OutputFormat) {
+    let classified = classify(error);
+    match (format, classified) {
+        // The partial reply was already followed by an [interrupted] marker.
+        (OutputFormat::Text, Some(GptError::Interrupted)) => {}
+        (OutputFormat::Text, Some(error)) => {
+            eprintln!("{} {}", "Error:".red().bold(), error);
+            if let Some(hint) = error.hint() {
+                eprintln!("{} {}", "Hint:".yellow().bold(), hint);
+            }
+        }
+        (OutputFormat::Text, None) => eprintln!("{} {}", "Error:".red().bold(), error),
+        (_, _) => eprintln!("{}", error_json(error)),
+    }
+}
+
+pub fn print_warning(message: &str) {
+    eprintln!("{} {}", "Warning:".yellow().bold(), message);
+}
+
+#[derive(Serialize, Debug)]
+pub struct RequestStats {
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub images: u32,
+    pub cost: f64,
+    pub elapsed_secs: f64,
+    pub cache: CacheStatus,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+impl RequestStats {
+    pub fn new(entry: &UsageEntry, elapsed: Duration, cache: CacheStatus) -> Self {
+        RequestStats {
+            model: entry.model.clone(),
+            prompt_tokens: entry.prompt_tokens,
+            completion_tokens: entry.completion_tokens,
+            images: entry.images,
+            cost: entry.cost,
+            elapsed_secs: elapsed.as_secs_f64(),
+            cache,
+        }
+    }
+}
+
+pub fn print_stats(stats: &RequestStats, format: OutputFormat) {
+    if format != OutputFormat::Text {
+        eprintln!("{}", json!({ "stats": stats }));
+        return;
+    }
+    let mut parts = vec![stats.model.clone()];
+    if stats.images > 0 {
+        parts.push(format!("{} image(s)", stats.images));
+    } else {
+        parts.push(format!(
+            "{} prompt + {} completion tokens",
+            stats.prompt_tokens, stats.completion_tokens
+        ));
+    }
+    parts.push(format!("${:.4}", stats.cost));
+    parts.push(format!("{:.2}s", stats.elapsed_secs));
+    match stats.cache {
+        CacheStatus::Hit => parts.push("cache hit".to_string()),
+        CacheStatus::Miss => parts.push("cache miss".to_string()),
+        CacheStatus::Off => {}
+    }
+    eprintln!("{}", parts.join(" · ").dimmed());
+}
+
+pub fn markdown_enabled(options: &CliOptions) -> bool {
+    options.output == OutputFormat::Text && !options.raw && io::stdout().is_terminal()
+}
+
+pub fn render_markdown(text: &str) -> String {
+    let mut renderer = MarkdownRenderer::new();
+    let mut rendered = renderer.push(text);
+    rendered.push_str(&renderer.finish());
+    rendered
+}
+
+fn flush_stdout() -> Result<(), Box<dyn Error>> {
+    io::stdout()
+        .flush()
+        .map_err(|e| io::Error::other(format!("Failed to flush stdout: {}", e)))?;
+    Ok(())
+}
+
+pub struct StreamPrinter {
+    output: OutputFormat,
+    markdown: Option<MarkdownRenderer>,
+    silent: bool,
+}
+
+impl StreamPrinter {
+    pub fn new(options: &CliOptions) -> Self {
+        StreamPrinter {
+            output: options.output,
+            markdown: markdown_enabled(options).then(MarkdownRenderer::new),
+            silent: options.code_only(),
+        }
+    }
+
+    pub fn delta(&mut self, content: &str) -> Result<(), Box<dyn Error>> {
+        if self.silent {
+            return Ok(());
+        }
+        match (self.output, &mut self.markdown) {
+            (OutputFormat::Text, Some(renderer)) => print!("{}", renderer.push(content)),
+            (OutputFormat::Text, None) => print!("{}", content),
+            (OutputFormat::Jsonl, _) => {
+                print_json(&json!({ "type": "delta", "content": content }), self.output)?
+            }
+            (OutputFormat::Json, _) => return Ok(()),
+        }
+        flush_stdout()
+    }
+
+    pub fn finish(&mut self, result: &ChatResult) -> Result<(), Box<dyn Error>> {
+        if self.silent {
+            return Ok(());
+        }
+        match (self.output, &mut self.markdown) {
+            (OutputFormat::Text, Some(renderer)) => {
+                let rendered = renderer.finish();
+                print!("{}", rendered);
+                if !rendered.ends_with('\n') && !result.content.ends_with('\n') {
+                    println!();
+                }
+            }
+            (OutputFormat::Text, None) => println!(),
+            (OutputFormat::Json, _) => print_json(result, self.output)?,
+            (OutputFormat::Jsonl, _) => {
+                let mut done = serde_json::to_value(result)?;
+                done["type"] = json!("done");
+                print_json(&done, self.output)?;
+            }
+        }
+        flush_stdout()
+    }
+}
@@ -1,5 +1,154 @@
 use colored::Colorize;
 
+pub const FLAGS: &[(&str, &str)] = &[
+    (
+        "--model <name>",
+        "Chat model to use instead of the default.",
+    ),
+    (
+        "--models <a,b,...>",
+        "Models for gpt compare or gpt eval; local:<name> is compare only.",
+    ),
+    (
+        "--max-tokens <n>",
+        "Maximum reply tokens, checked against the context window.",
+    ),
+    (
+        "--profile <name>",
+        "Profile to record usage under (default $GPT_PROFILE or $USER).",
+    ),
+    (
+        "--budget <usd>",
+        "Refuse requests once the profile spent this much this month.",
+    ),
+    (
+        "--since <period>",
+        "Usage report period, e.g. 12h, 7d or 4w (default this month).",
+    ),
+    (
+        "--by <group>",
+        "Group the usage report by model, profile or day.",
+    ),
+    (
+        "--var <key=value>",
+        "Set a template variable for gpt run (repeatable).",
+    ),
+    (
+        "--json-schema <file>",
+        "Return JSON validated against a JSON Schema file.",
+    ),
+    (
+        "--no-schema-retry",
+        "Fail instead of retrying once on a schema mismatch.",
+    ),
+    (
+        "--output <format>",
+        "Output format: text (default), json or jsonl.",
+    ),
+    (
+        "--cache",
+        "Reuse cached replies for identical requests (--no-cache to skip).",
+    ),
+    (
+        "--stats",
+        "Print tokens, cost, time and cache status after each request.",
+    ),
+    (
+        "--concurrency <n>",
+        "Batch or eval requests to run at once (default 4).",
+    ),
+    (
+        "--out <file>",
+        "Write batch results to a file, resuming completed lines.",
+    ),
+    (
+        "--template <name>",
+        "Template for gpt watch to run on each change.",
+    ),
+    (
+        "--prompt <text>",
+        "Prompt for gpt watch to run on each change.",
+    ),
+    (
+        "--junit <file>",
+        "Write gpt eval results as JUnit XML for CI.",
+    ),
+    (
+        "--port <n>",
+        "Port for gpt serve to listen on (default 8080).",
+    ),
+    (
+        "--upstream <url>",
+        "OpenAI-compatible API base gpt serve forwards to.",
+    ),
+    (
+        "--redact <mode>",
+        "Mask, block or confirm secrets before sending (mask|block|warn|off).",
+    ),
+    ("--yes", "Skip confirmation (gpt commit, gpt sh, gpt edit)."),
+    (
+        "--dry-run",
+        "Show the changes gpt edit would make without writing them.",
+    ),
+    (
+        "--base <branch>",
+        "Branch to review changes against (gpt review, default main).",
+    ),
+    (
+        "--sarif",
+        "Write gpt review findings as a SARIF 2.1.0 report.",
+    ),
+    (
+        "--wait",
+        "Poll an async batch until it finishes (batch status/fetch).",
+    ),
+    (
+        "--record <file>",
+        "Record API requests and responses to a JSONL cassette.",
+    ),
+    (
+        "--replay <file>",
+        "Serve API responses from a cassette instead of the network.",
+    ),
+    (
+        "--raw",
+        "Print replies as raw markdown instead of rendering them.",
+    ),
+    (
+        "--code",
+        "Print only the fenced code blocks from the reply.",
+    ),
+    (
+        "--code-index <n>",
+        "Print only the Nth code block (starting at 1).",
+    ),
+    (
+        "--save-code <dir>",
+        "Write each code block to a file in <dir>.",
+    ),
+    (
+        "--max-retries <n>",
+        "Retries for rate limits, 5xx and connection errors (default 3).",
+    ),
+    (
+        "--connect-timeout <s>",
+        "Seconds to wait for a connection (default 10).",
+    ),
+    (
+        "--first-byte-timeout <s>",
+        "Seconds to wait for a streamed reply to start (default 60).",
+    ),
+    (
+        "--idle-timeout <s>",
+        "Seconds to wait between streamed chunks (default 30).",
+    ),
+    (
+        "--total-timeout <s>",
+        "Seconds allowed for non-streamed replies like images (default 300).",
+    ),
+];
+
+#[allow(clippy::print_literal)]
 pub fn print_help() {
     println!("{:━^60}", " GPTCLI ".yellow());
     println!("Usage:");
@@ -19,6 +168,18 @@ pub fn print_help() {
         "  {}     Display this help message.",
         "-h, -help".bold().blue()
     );
+    println!("\nFlags:");
+    for (flag, description) in FLAGS {
+        println!(
+            "  {}  {}",
+            format!("{:<24}", flag).bold().yellow(),
+            description
+        );
+    }
+    println!(
+        "  {}  Ends the flags; the rest is the prompt, even words like --yes.",
+        format!("{:<24}", "--").bold().yellow()
+    );
     println!("\nArguments:");
     println!(
         "  {}  A text prompt for GPT-3.5-Turbo.",
@@ -33,6 +194,74 @@ pub fn print_help() {
         "  {}  A text prompt for DALL-E 3.",
         "d <prompt>".bold().red()
     );
+    println!(
+        "  {}  Count the tokens in text or a file.",
+        "tokens <text|file>".bold().yellow()
+    );
+    println!(
+        "  {}  Summarize recorded usage and estimated cost.",
+        "usage".bold().yellow()
+    );
+    println!(
+        "  {}  Write a commit message for the staged changes.",
+        "commit [notes]".bold().yellow()
+    );
+    println!(
+        "  {}  Edit files as instructed, after showing a diff.",
+        "edit <file>... <instruction>".bold().yellow()
+    );
+    println!(
+        "  {}  Run a prompt on several models and compare them.",
+        "compare <prompt>".bold().yellow()
+    );
+    println!(
+        "  {}  Run a YAML suite of prompt tests and report failures.",
+        "eval <suite.yaml>".bold().yellow()
+    );
+    println!(
+        "  {}  Serve an OpenAI-compatible API using your key and settings.",
+        "serve".bold().yellow()
+    );
+    println!(
+        "  {}  Re-run a prompt whenever the files change.",
+        "watch <path>...".bold().yellow()
+    );
+    println!(
+        "  {}  Suggest a shell command and offer to run it.",
+        "sh <task>".bold().yellow()
+    );
+    println!(
+        "  {}  Explain each part of a shell command.",
+        "explain-cmd <command>".bold().yellow()
+    );
+    println!(
+        "  {}  Print a completion script for your shell.",
+        "completions bash|zsh|fish".bold().yellow()
+    );
+    println!(
+        "  {}  Print completions and a Ctrl-G binding for gpt sh.",
+        "shell-init bash|zsh|fish".bold().yellow()
+    );
+    println!(
+        "  {}  Review the changes on this branch since main.",
+        "review".bold().yellow()
+    );
+    println!(
+        "  {}  Run a saved prompt template.",
+        "run <template> [prompt]".bold().yellow()
+    );
+    println!(
+        "  {}  List, show or create prompt templates.",
+        "templates list|show|new".bold().yellow()
+    );
+    println!(
+        "  {}  Run a JSONL file of prompts concurrently.",
+        "batch <file>".bold().yellow()
+    );
+    println!(
+        "  {}  Run a batch file through the cheaper async Batch API.",
+        "batch submit|status|fetch|cancel".bold().yellow()
+    );
     println!("\nExamples:");
     println!(
         "  {} What is the capital of California?",
@@ -47,5 +276,14 @@ pub fn print_help() {
         "  {} An astronaut on Mars in a rusty spacesuit holding a crab",
         "gpt d".bold().red()
     );
+    println!(
+        "  {} List three US state capitals",
+        "gpt --json-schema capitals.json".bold().yellow()
+    );
+    println!("\nExit codes:");
+    println!("  0 ok, 1 failure, 2 invalid input, 3 missing API key, 4 auth, 5 rate limited,");
+    println!("  6 context length, 7 content filtered, 8 network, 9 file I/O, 10 API error,");
+    println!("  11 budget exceeded, 12 unusable reply, 13 command failed,");
+    println!("  14 some batch, eval or compare requests failed, 130 interrupted (Ctrl-C)");
     println!("{:━^60}", "".yellow());
 }
@@ -0,0 +1,280 @@
+use crate::config::Config;
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::output::print_warning;
+use crate::usage::current_profile;
+use colored::Colorize;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fs,
+    io::{BufRead, BufReader},
+    ops::Range,
+    str::FromStr,
+    sync::Mutex,
+};
+
+// Private keys come first so the base64 inside them isn't reported as something else.
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    (
+        "private_key",
+        r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+    ),
+    (
+        "openai_key",
+        r"\bsk-(?:proj-|svcacct-|admin-)?[A-Za-z0-9_-]{20,}",
+    ),
+    (
+        "github_token",
+        r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})",
+    ),
+    ("slack_token", r"\bxox[abposr]-[A-Za-z0-9-]{10,}"),
+    ("google_api_key", r"\bAIza[0-9A-Za-z_-]{35}"),
+    ("aws_access_key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+    (
+        "aws_secret_key",
+        r#"(?i)aws_secret_access_key["']?\s*[:=]\s*["']?[A-Za-z0-9/+=]{40}"#,
+    ),
+    (
+        "jwt",
+        r"\beyJ[A-Za-z0-9_-]{10,}\.eyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}",
+    ),
+    (
+        "email",
+        r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b",
+    ),
+];
+
+pub type Confirm<'a> = dyn FnMut(&str) -> Result<bool, Box<dyn Error>> + 'a;
+
+// Secrets the user agreed to send. Bodies are checked before the cache lookup and again
+// just before sending, and concurrent requests (batch, eval, compare) often share text, so
+// each secret is only asked about once. Holding the lock also makes prompts take turns.
+static APPROVED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactMode {
+    Off,
+    Mask,
+    Block,
+    Warn,
+}
+
+impl FromStr for RedactMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(RedactMode::Off),
+            "mask" => Ok(RedactMode::Mask),
+            "block" => Ok(RedactMode::Block),
+            "warn" => Ok(RedactMode::Warn),
+            _ => Err(format!(
+                "Invalid redact mode: {} (expected mask, block, warn or off)",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Secret {
+    pub kind: String,
+    pub range: Range<usize>,
+}
+
+pub struct Redactor {
+    detectors: Vec<(String, Regex)>,
+}
+
+impl Redactor {
+    pub fn new(patterns: &BTreeMap<String, String>) -> Result<Self, GptError> {
+        let mut detectors: Vec<(String, Regex)> = BUILTIN_PATTERNS
+            .iter()
+            .map(|(kind, pattern)| (kind.to_string(), Regex::new(pattern).unwrap()))
+            .collect();
+        for (kind, pattern) in patterns {
+            let regex = Regex::new(pattern).map_err(|e| {
+                GptError::invalid_input(format!("Invalid redact pattern {}: {}", kind, e))
+            })?;
+            detectors.push((kind.clone(), regex));
+        }
+        Ok(Redactor { detectors })
+    }
+
+    // Overlapping matches are reported once, by the first detector that found them.
+    pub fn find(&self, text: &str) -> Vec<Secret> {
+        let mut secrets: Vec<Secret> = Vec::new();
+        for (kind, regex) in &self.detectors {
+            for found in regex.find_iter(text) {
+                let range = found.range();
+                if range.is_empty()
+                    || secrets
+                        .iter()
+                        .any(|s| s.range.start < range.end && range.start < s.range.end)
+                {
+                    continue;
+                }
+                secrets.push(Secret {
+                    kind: kind.clone(),
+                    range,
+                });
+            }
+        }
+        secrets.sort_by_key(|secret| secret.range.start);
+        secrets
+    }
+
+    pub fn mask(&self, text: &str) -> String {
+        let mut masked = String::with_capacity(text.len());
+        let mut last = 0;
+        for secret in self.find(text) {
+            masked.push_str(&text[last..secret.range.start]);
+            masked.push_str(&format!("[REDACTED:{}]", secret.kind));
+            last = secret.range.end;
+        }
+        masked.push_str(&text[last..]);
+        masked
+    }
+}
+
+// The text a request sends on the user's behalf: message contents, image prompts and
+// embedding inputs.
+fn text_fields(body: &mut Value) -> Vec<&mut String> {
+    let mut fields = Vec::new();
+    let Some(object) = body.as_object_mut() else {
+        return fields;
+    };
+    for (key, value) in object.iter_mut() {
+        match (key.as_str(), value) {
+            ("messages", Value::Array(messages)) => {
+                for message in messages {
+                    match message.get_mut("content") {
+                        Some(Value::String(content)) => fields.push(content),
+                        Some(Value::Array(parts)) => {
+                            fields.extend(parts.iter_mut().filter_map(|part| {
+                                match part.get_mut("text") {
+                                    Some(Value::String(text)) => Some(text),
+                                    _ => None,
+                                }
+                            }))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            ("prompt" | "input", Value::String(text)) => fields.push(text),
+            ("input", Value::Array(inputs)) => {
+                fields.extend(inputs.iter_mut().filter_map(|input| match input {
+                    Value::String(text) => Some(text),
+                    _ => None,
+                }))
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+fn describe(counts: &BTreeMap<String, usize>) -> String {
+    let parts: Vec<String> = counts
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind))
+        .collect();
+    parts.join(", ")
+}
+
+// Reads the answer from the terminal, since stdin is often the piped input being checked.
+fn confirm(summary: &str) -> Result<bool, Box<dyn Error>> {
+    let terminal = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    let Ok(terminal) = fs::File::open(terminal) else {
+        return Err(GptError::invalid_input(format!(
+            "The request contains {} and there is no terminal to confirm on; use --redact mask or block",
+            summary
+        ))
+        .into());
+    };
+    eprint!("{} ", "Send it anyway? [y/N]".bold());
+    let mut answer = String::new();
+    BufReader::new(terminal)
+        .read_line(&mut answer)
+        .map_err(|e| GptError::io(format!("Failed to read the answer: {}", e)))?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub fn redact_mode(options: &CliOptions, config: &Config) -> RedactMode {
+    options
+        .redact
+        .or_else(|| config.profile(&current_profile(options)).redact)
+        .unwrap_or(RedactMode::Off)
+}
+
+pub fn redact_fields(
+    body: &mut Value,
+    mode: RedactMode,
+    redactor: &Redactor,
+    confirm: &mut Confirm<'_>,
+) -> Result<(), Box<dyn Error>> {
+    if mode == RedactMode::Off {
+        return Ok(());
+    }
+    let mut fields = text_fields(body);
+    let mut counts = BTreeMap::new();
+    let mut found = BTreeSet::new();
+    let mut approved = APPROVED.lock().unwrap_or_else(|e| e.into_inner());
+    for field in &fields {
+        for secret in redactor.find(field) {
+            let text = field[secret.range].to_string();
+            if mode == RedactMode::Warn && approved.contains(&text) {
+                continue;
+            }
+            *counts.entry(secret.kind).or_insert(0) += 1;
+            found.insert(text);
+        }
+    }
+    if counts.is_empty() {
+        return Ok(());
+    }
+    let summary = describe(&counts);
+
+    match mode {
+        RedactMode::Off => Ok(()),
+        RedactMode::Mask => {
+            for field in fields.iter_mut() {
+                **field = redactor.mask(field);
+            }
+            print_warning(&format!("Masked {} before sending the request", summary));
+            Ok(())
+        }
+        RedactMode::Block => Err(GptError::invalid_input(format!(
+            "The request contains {}, so it was not sent (use --redact mask to send it masked)",
+            summary
+        ))
+        .into()),
+        RedactMode::Warn => {
+            print_warning(&format!("The request contains {}", summary));
+            if confirm(&summary)? {
+                approved.extend(found);
+                Ok(())
+            } else {
+                Err(GptError::invalid_input("Request not sent").into())
+            }
+        }
+    }
+}
+
+// Runs on request bodies before they are looked up in the cache or written to a batch file,
+// and again just before they are sent, according to --redact or the profile's setting.
+pub fn redact_request(body: &mut Value, options: &CliOptions) -> Result<(), Box<dyn Error>> {
+    let config = Config::load()?;
+    let mode = redact_mode(options, &config);
+    if mode == RedactMode::Off {
+        return Ok(());
+    }
+    let redactor = Redactor::new(&config.redact.patterns)?;
+    redact_fields(body, mode, &redactor, &mut confirm)
+}
@@ -0,0 +1,124 @@
+use crate::constants::{RETRY_BASE_DELAY_MS, RETRY_MAX_DELAY_MS, RETRY_MAX_HINT_MS};
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_hint: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(RETRY_BASE_DELAY_MS),
+            max_delay: Duration::from_millis(RETRY_MAX_DELAY_MS),
+            max_hint: Duration::from_millis(RETRY_MAX_HINT_MS),
+        }
+    }
+
+    pub fn backoff_delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        // Half fixed, half random so concurrent clients don't retry in lockstep.
+        exponential / 2 + exponential.mul_f64(jitter() / 2.0)
+    }
+
+    // The server's hint is followed as given, since retrying sooner only earns another 429.
+    // None means it asked for a longer wait than is worth sitting through.
+    pub fn delay_for(&self, attempt: u32, hint: Option<Duration>) -> Option<Duration> {
+        match hint {
+            Some(delay) if delay > self.max_hint => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff_delay(attempt)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamInterrupted {
+    pub message: String,
+    pub emitted_tokens: bool,
+}
+
+impl fmt::Display for StreamInterrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Response stream interrupted: {}", self.message)
+    }
+}
+
+impl std::error::Error for StreamInterrupted {}
+
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    f64::from(nanos % 1000) / 1000.0
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout()
+}
+
+pub fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return (seconds >= 0.0).then(|| Duration::from_secs_f64(seconds));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_end] {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return None,
+        };
+        total += Duration::from_secs_f64(seconds);
+        rest = &rest[unit_end..];
+    }
+    Some(total)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+pub fn retry_after_delay(headers: &HeaderMap) -> Option<Duration> {
+    header(headers, "retry-after-ms")
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .or_else(|| header(headers, "retry-after").and_then(parse_reset_duration))
+}
+
+pub fn rate_limit_delay(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(delay) = retry_after_delay(headers) {
+        return Some(delay);
+    }
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .iter()
+        .filter_map(|name| header(headers, name).and_then(parse_reset_duration))
+        .max()
+}
@@ -0,0 +1,303 @@
+use crate::constants::{
+    DEFAULT_REVIEW_BASE, GPT_API_URL, REVIEW_CHUNK_TOKENS, REVIEW_REPLY_TOKENS,
+    REVIEW_SYSTEM_PROMPT, STRUCTURED_MODEL,
+};
+use crate::error::GptError;
+use crate::git::{self, FileDiff};
+use crate::options::CliOptions;
+use crate::output::{print_json, print_warning, OutputFormat};
+use crate::tokens::{context_window, Encoding};
+use crate::utils::{create_spinner, enforce_budget, run_structured_chat, structured_chat_request};
+use colored::Colorize;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{error::Error, path::Path};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    fn sarif_level(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "note",
+        }
+    }
+
+    fn label(self) -> colored::ColoredString {
+        match self {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+            Severity::Info => "info".blue().bold(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub file: String,
+    pub line: u64,
+    pub severity: Severity,
+    pub message: String,
+}
+
+pub fn findings_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "findings": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "file": { "type": "string" },
+                        "line": { "type": "integer", "minimum": 1 },
+                        "severity": { "type": "string", "enum": ["error", "warning", "info"] },
+                        "message": { "type": "string" }
+                    },
+                    "required": ["file", "line", "severity", "message"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["findings"],
+        "additionalProperties": false
+    })
+}
+
+// The header is everything before the first @@ line (diff --git, index, ---/+++).
+pub fn split_hunks(text: &str) -> (String, Vec<String>) {
+    let mut header = String::new();
+    let mut hunks: Vec<String> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        if line.starts_with("@@") {
+            hunks.push(String::new());
+        }
+        match hunks.last_mut() {
+            Some(hunk) => hunk.push_str(line),
+            None => header.push_str(line),
+        }
+    }
+    (header, hunks)
+}
+
+// Packs whole files into chunks of at most `budget` tokens. Files that are too large on
+// their own are split between hunks, repeating the file header in each part.
+pub fn chunk_diff(files: &[FileDiff], budget: usize, count: &dyn Fn(&str) -> usize) -> Vec<String> {
+    let mut pieces: Vec<(String, usize)> = Vec::new();
+    for file in files.iter().filter(|file| !file.binary) {
+        let tokens = count(&file.text);
+        if tokens <= budget {
+            pieces.push((file.text.clone(), tokens));
+            continue;
+        }
+        let (header, hunks) = split_hunks(&file.text);
+        let header_tokens = count(&header);
+        let mut part = (header.clone(), header_tokens);
+        for hunk in hunks {
+            let hunk_tokens = count(&hunk);
+            if header_tokens + hunk_tokens > budget {
+                print_warning(&format!(
+                    "Skipping a hunk in {} that is too large to review",
+                    file.path
+                ));
+                continue;
+            }
+            if part.1 + hunk_tokens > budget {
+                pieces.push(std::mem::replace(
+                    &mut part,
+                    (header.clone(), header_tokens),
+                ));
+            }
+            part.0.push_str(&hunk);
+            part.1 += hunk_tokens;
+        }
+        if part.1 > header_tokens {
+            pieces.push(part);
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut current = (String::new(), 0);
+    for (piece, tokens) in pieces {
+        if !current.0.is_empty() && current.1 + tokens > budget {
+            chunks.push(std::mem::take(&mut current.0));
+            current.1 = 0;
+        }
+        current.0.push_str(&piece);
+        current.1 += tokens;
+    }
+    if !current.0.is_empty() {
+        chunks.push(current.0);
+    }
+    chunks
+}
+
+fn chunk_budget(model: &str) -> usize {
+    let overhead = Encoding::for_model(model).count(REVIEW_SYSTEM_PROMPT) + REVIEW_REPLY_TOKENS;
+    context_window(model)
+        .map_or(REVIEW_CHUNK_TOKENS, |window| {
+            window.saturating_sub(overhead)
+        })
+        .min(REVIEW_CHUNK_TOKENS)
+}
+
+pub async fn review_diff(
+    client: &Client,
+    api_url: &str,
+    diff: &str,
+    options: &CliOptions,
+) -> Result<Vec<Finding>, Box<dyn Error>> {
+    let model = options.model.as_deref().unwrap_or(STRUCTURED_MODEL);
+    let encoding = Encoding::for_model(model);
+    let chunks = chunk_diff(&git::parse_diff(diff), chunk_budget(model), &|text| {
+        encoding.count(text)
+    });
+
+    let mut findings = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        enforce_budget(options, &[model])?;
+        let request = structured_chat_request(
+            model,
+            REVIEW_SYSTEM_PROMPT,
+            format!("Review this diff:\n\n{}", chunk),
+            "review",
+            findings_schema(),
+        );
+
+        let spinner = create_spinner(
+            "green",
+            format!("Reviewing part {} of {}...", index + 1, chunks.len()),
+        );
+        let reply = run_structured_chat(client, api_url, request, options).await;
+        spinner.finish_and_clear();
+        let chunk_findings: Vec<Finding> = serde_json::from_value(reply?["findings"].take())?;
+        findings.extend(chunk_findings);
+    }
+
+    findings.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+    Ok(findings)
+}
+
+pub async fn review_changes(
+    client: &Client,
+    api_url: &str,
+    repo: &Path,
+    base: &str,
+    options: &CliOptions,
+) -> Result<Option<Vec<Finding>>, Box<dyn Error>> {
+    let diff = git::diff(repo, &[&format!("{}...HEAD", base)])?;
+    // None when there are no text changes to send.
+    if git::parse_diff(&diff).iter().all(|file| file.binary) {
+        return Ok(None);
+    }
+    Ok(Some(review_diff(client, api_url, &diff, options).await?))
+}
+
+pub fn sarif_report(findings: &[Finding]) -> Value {
+    let results: Vec<Value> = findings
+        .iter()
+        .map(|finding| {
+            json!({
+                "ruleId": "review",
+                "level": finding.severity.sarif_level(),
+                "message": { "text": finding.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": finding.file },
+                        "region": { "startLine": finding.line.max(1) }
+                    }
+                }]
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "gpt",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/aumbriac/GPTCLI",
+                    "rules": [{
+                        "id": "review",
+                        "shortDescription": { "text": "Model code review finding" }
+                    }]
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+fn print_findings(findings: &[Finding]) {
+    let mut files = 0;
+    let mut current: Option<&str> = None;
+    for finding in findings {
+        if current != Some(finding.file.as_str()) {
+            if current.is_some() {
+                println!();
+            }
+            println!("{}", finding.file.bold());
+            current = Some(&finding.file);
+            files += 1;
+        }
+        println!(
+            "  {:>5}  {:<7}  {}",
+            finding.line,
+            finding.severity.label(),
+            finding.message
+        );
+    }
+    if findings.is_empty() {
+        println!("No issues found.");
+    } else {
+        eprintln!(
+            "\n{}",
+            format!("{} finding(s) in {} file(s)", findings.len(), files).dimmed()
+        );
+    }
+}
+
+pub async fn process_review_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    if args.len() > 2 {
+        return Err(
+            GptError::invalid_input("Usage: gpt review [--base <branch>] [--sarif]").into(),
+        );
+    }
+    let base = options.base.as_deref().unwrap_or(DEFAULT_REVIEW_BASE);
+    let findings = match review_changes(client, GPT_API_URL, Path::new("."), base, options).await? {
+        Some(findings) => findings,
+        None if options.output == OutputFormat::Text && !options.sarif => {
+            println!("Nothing to review: no changes between {} and HEAD.", base);
+            return Ok(());
+        }
+        None => Vec::new(),
+    };
+
+    if options.sarif {
+        return print_json(&sarif_report(&findings), OutputFormat::Json);
+    }
+    match options.output {
+        OutputFormat::Text => print_findings(&findings),
+        OutputFormat::Json => print_json(&json!({ "findings": findings }), options.output)?,
+        OutputFormat::Jsonl => {
+            for finding in &findings {
+                print_json(finding, options.output)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,423 @@
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+
+// Keywords the validator checks, plus annotations that don't constrain the value. Anything
+// else is rejected rather than silently ignored, so "valid" always means checked.
+const SUPPORTED_KEYWORDS: &[&str] = &[
+    "$ref",
+    "type",
+    "enum",
+    "const",
+    "required",
+    "properties",
+    "additionalProperties",
+    "minProperties",
+    "maxProperties",
+    "dependentRequired",
+    "items",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+];
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "$defs",
+    "definitions",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct SchemaError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "at {}: {}", path, self.message)
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+pub fn validate(schema: &Value, instance: &Value) -> Result<(), SchemaError> {
+    check_supported(schema)?;
+    validate_at(schema, schema, instance, "", &[])
+}
+
+// Errors on keywords the validator would otherwise skip, with the schema path they're at.
+pub fn check_supported(schema: &Value) -> Result<(), SchemaError> {
+    check_keywords(schema, "#")
+}
+
+fn check_keywords(schema: &Value, path: &str) -> Result<(), SchemaError> {
+    let Value::Object(schema) = schema else {
+        return Ok(());
+    };
+    for (key, value) in schema {
+        let child_path = format!("{}/{}", path, escape_pointer(key));
+        if !SUPPORTED_KEYWORDS.contains(&key.as_str()) && !ANNOTATIONS.contains(&key.as_str()) {
+            return Err(error(
+                path,
+                format!("unsupported schema keyword \"{}\"", key),
+            ));
+        }
+        match (key.as_str(), value) {
+            ("properties" | "$defs" | "definitions", Value::Object(schemas)) => {
+                for (name, sub_schema) in schemas {
+                    check_keywords(
+                        sub_schema,
+                        &format!("{}/{}", child_path, escape_pointer(name)),
+                    )?;
+                }
+            }
+            ("allOf" | "anyOf" | "oneOf", Value::Array(schemas)) => {
+                for (index, sub_schema) in schemas.iter().enumerate() {
+                    check_keywords(sub_schema, &format!("{}/{}", child_path, index))?;
+                }
+            }
+            ("items" | "additionalProperties" | "not", sub_schema) => {
+                check_keywords(sub_schema, &child_path)?
+            }
+            ("pattern", Value::String(pattern)) => {
+                Regex::new(pattern)
+                    .map_err(|e| error(path, format!("invalid pattern {}: {}", pattern, e)))?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn error(path: &str, message: String) -> SchemaError {
+    SchemaError {
+        path: path.to_string(),
+        message,
+    }
+}
+
+fn escape_pointer(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(n) if n.as_f64().is_some_and(|f| f.fract() == 0.0) => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn matches_type(expected: &str, value: &Value) -> bool {
+    let actual = type_name(value);
+    actual == expected || (expected == "number" && actual == "integer")
+}
+
+fn resolve_ref<'a>(root: &'a Value, reference: &str, path: &str) -> Result<&'a Value, SchemaError> {
+    reference
+        .strip_prefix('#')
+        .and_then(|pointer| root.pointer(pointer))
+        .ok_or_else(|| error(path, format!("unresolvable schema reference {}", reference)))
+}
+
+fn validate_at(
+    root: &Value,
+    schema: &Value,
+    instance: &Value,
+    path: &str,
+    refs: &[&str],
+) -> Result<(), SchemaError> {
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(error(path, "no value is allowed here".to_string())),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    // `refs` are the references followed without moving to a child value; meeting one
+    // again would recurse forever.
+    if let Some(Value::String(reference)) = schema.get("$ref") {
+        if refs.contains(&reference.as_str()) {
+            return Err(error(
+                path,
+                format!("schema reference {} refers back to itself", reference),
+            ));
+        }
+        let target = resolve_ref(root, reference, path)?;
+        let mut refs = refs.to_vec();
+        refs.push(reference);
+        validate_at(root, target, instance, path, &refs)?;
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|t| matches_type(t, instance)) {
+            return Err(error(
+                path,
+                format!(
+                    "expected {}, found {}",
+                    allowed.join(" or "),
+                    type_name(instance)
+                ),
+            ));
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(instance) {
+            return Err(error(
+                path,
+                format!(
+                    "{} is not one of {}",
+                    instance,
+                    Value::Array(options.clone())
+                ),
+            ));
+        }
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if expected != instance {
+            return Err(error(path, format!("expected constant {}", expected)));
+        }
+    }
+
+    match instance {
+        Value::Object(object) => {
+            let count = object.len() as u64;
+            if let Some(min) = schema.get("minProperties").and_then(Value::as_u64) {
+                if count < min {
+                    return Err(error(
+                        path,
+                        format!("expected at least {} properties, found {}", min, count),
+                    ));
+                }
+            }
+            if let Some(max) = schema.get("maxProperties").and_then(Value::as_u64) {
+                if count > max {
+                    return Err(error(
+                        path,
+                        format!("expected at most {} properties, found {}", max, count),
+                    ));
+                }
+            }
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        return Err(error(
+                            path,
+                            format!("missing required property \"{}\"", key),
+                        ));
+                    }
+                }
+            }
+
+            if let Some(Value::Object(dependencies)) = schema.get("dependentRequired") {
+                for (key, dependents) in dependencies {
+                    if !object.contains_key(key) {
+                        continue;
+                    }
+                    let dependents = dependents.as_array().into_iter().flatten();
+                    for dependent in dependents.filter_map(Value::as_str) {
+                        if !object.contains_key(dependent) {
+                            return Err(error(
+                                path,
+                                format!("property \"{}\" requires property \"{}\"", key, dependent),
+                            ));
+                        }
+                    }
+                }
+            }
+
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, value) in object {
+                let child_path = format!("{}/{}", path, escape_pointer(key));
+                match properties.and_then(|p| p.get(key)) {
+                    Some(property_schema) => {
+                        validate_at(root, property_schema, value, &child_path, &[])?
+                    }
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return Err(error(path, format!("unexpected property \"{}\"", key)))
+                        }
+                        Some(additional) => validate_at(root, additional, value, &child_path, &[])?,
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    return Err(error(
+                        path,
+                        format!("expected at least {} items, found {}", min, items.len()),
+                    ));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max {
+                    return Err(error(
+                        path,
+                        format!("expected at most {} items, found {}", max, items.len()),
+                    ));
+                }
+            }
+            if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+                for (index, item) in items.iter().enumerate() {
+                    if items[..index].contains(item) {
+                        return Err(error(
+                            path,
+                            format!("item {} duplicates an earlier item", index),
+                        ));
+                    }
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(root, item_schema, item, &format!("{}/{}", path, index), &[])?;
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if length < min {
+                    return Err(error(
+                        path,
+                        format!("expected at least {} characters, found {}", min, length),
+                    ));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if length > max {
+                    return Err(error(
+                        path,
+                        format!("expected at most {} characters, found {}", max, length),
+                    ));
+                }
+            }
+            if let Some(Value::String(pattern)) = schema.get("pattern") {
+                let regex = Regex::new(pattern)
+                    .map_err(|e| error(path, format!("invalid pattern {}: {}", pattern, e)))?;
+                if !regex.is_match(text) {
+                    return Err(error(
+                        path,
+                        format!("\"{}\" does not match pattern {}", text, pattern),
+                    ));
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+            if let Some(min) = bound("minimum") {
+                if number < min {
+                    return Err(error(path, format!("{} is less than {}", number, min)));
+                }
+            }
+            if let Some(max) = bound("maximum") {
+                if number > max {
+                    return Err(error(path, format!("{} is greater than {}", number, max)));
+                }
+            }
+            if let Some(min) = bound("exclusiveMinimum") {
+                if number <= min {
+                    return Err(error(
+                        path,
+                        format!("{} is not greater than {}", number, min),
+                    ));
+                }
+            }
+            if let Some(max) = bound("exclusiveMaximum") {
+                if number >= max {
+                    return Err(error(path, format!("{} is not less than {}", number, max)));
+                }
+            }
+            if let Some(divisor) = bound("multipleOf").filter(|divisor| *divisor > 0.0) {
+                let quotient = number / divisor;
+                if (quotient - quotient.round()).abs() > 1e-9 {
+                    return Err(error(
+                        path,
+                        format!("{} is not a multiple of {}", number, divisor),
+                    ));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        for sub_schema in schemas {
+            validate_at(root, sub_schema, instance, path, refs)?;
+        }
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        let mut errors = schemas
+            .iter()
+            .map(|sub_schema| validate_at(root, sub_schema, instance, path, refs));
+        if !errors.any(|result| result.is_ok()) {
+            return Err(error(path, "value does not match any of anyOf".to_string()));
+        }
+    }
+
+    if let Some(not_schema) = schema.get("not") {
+        if validate_at(root, not_schema, instance, path, refs).is_ok() {
+            return Err(error(
+                path,
+                "value matches a schema it must not".to_string(),
+            ));
+        }
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        let matching = schemas
+            .iter()
+            .filter(|sub_schema| validate_at(root, sub_schema, instance, path, refs).is_ok())
+            .count();
+        if matching != 1 {
+            return Err(error(
+                path,
+                format!("value matches {} of oneOf, expected exactly 1", matching),
+            ));
+        }
+    }
+
+    Ok(())
+}
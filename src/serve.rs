@@ -0,0 +1,455 @@
+use crate::cache::{cache_key, CacheStatus, ResponseCache};
+use crate::chat::{ChatMessageRole, ChatResult, ChatUsage};
+use crate::config::Config;
+use crate::constants::{DEFAULT_SERVE_PORT, OPENAI_API_BASE};
+use crate::error::{classify, GptError};
+use crate::options::CliOptions;
+use crate::output::print_warning;
+use crate::redact;
+use crate::timeouts::{with_timeout, TimeoutPhase};
+use crate::usage::{current_profile, UsageEntry, UsageLedger};
+use crate::utils::{
+    chat_usage, enforce_budget, parse_chat_line, post_openai_request, record_request,
+};
+use colored::Colorize;
+use futures::StreamExt;
+use hyper::header::{HeaderMap, CACHE_CONTROL, CONTENT_TYPE, HOST, ORIGIN};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::{
+    convert::Infallible,
+    error::Error,
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    time::Instant,
+};
+
+// Header telling clients whether a reply came from the response cache.
+const CACHE_HEADER: &str = "x-gpt-cache";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    Chat,
+    Images,
+    Embeddings,
+}
+
+impl Endpoint {
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.trim_end_matches('/') {
+            "/v1/chat/completions" => Some(Endpoint::Chat),
+            "/v1/images/generations" => Some(Endpoint::Images),
+            "/v1/embeddings" => Some(Endpoint::Embeddings),
+            _ => None,
+        }
+    }
+
+    fn upstream_path(&self) -> &'static str {
+        match self {
+            Endpoint::Chat => "/chat/completions",
+            Endpoint::Images => "/images/generations",
+            Endpoint::Embeddings => "/embeddings",
+        }
+    }
+}
+
+struct ServeState {
+    client: Client,
+    upstream: String,
+    options: CliOptions,
+}
+
+fn cache_status(cache: &Option<ResponseCache>) -> CacheStatus {
+    if cache.is_some() {
+        CacheStatus::Miss
+    } else {
+        CacheStatus::Off
+    }
+}
+
+fn cache_header(status: CacheStatus) -> &'static str {
+    match status {
+        CacheStatus::Off => "off",
+        CacheStatus::Hit => "hit",
+        CacheStatus::Miss => "miss",
+    }
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+fn openai_error(
+    status: StatusCode,
+    message: &str,
+    error_type: &str,
+    code: Option<&str>,
+) -> Response<Body> {
+    json_response(
+        status,
+        &json!({ "error": { "message": message, "type": error_type, "code": code } }),
+    )
+}
+
+// Errors go back in the OpenAI shape, so clients handle them as if they came from the API.
+fn error_response(error: &(dyn Error + 'static)) -> Response<Body> {
+    let Some(classified) = classify(error) else {
+        return openai_error(
+            StatusCode::BAD_GATEWAY,
+            &error.to_string(),
+            "api_error",
+            None,
+        );
+    };
+    let (status, code) = match &classified {
+        GptError::ApiError { status, code, .. } => (
+            StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_GATEWAY),
+            code.clone(),
+        ),
+        GptError::Auth { .. } => (StatusCode::UNAUTHORIZED, None),
+        GptError::RateLimited { .. } | GptError::BudgetExceeded { .. } => {
+            (StatusCode::TOO_MANY_REQUESTS, None)
+        }
+        GptError::ContextLengthExceeded { .. } => (
+            StatusCode::BAD_REQUEST,
+            Some("context_length_exceeded".to_string()),
+        ),
+        GptError::ContentFiltered { .. } | GptError::InvalidInput { .. } => {
+            (StatusCode::BAD_REQUEST, None)
+        }
+        GptError::MissingApiKey
+        | GptError::Io { .. }
+        | GptError::CommandFailed { .. }
+        | GptError::RequestsFailed { .. } => (StatusCode::INTERNAL_SERVER_ERROR, None),
+        GptError::Network { .. } | GptError::InvalidReply { .. } | GptError::Interrupted => {
+            (StatusCode::BAD_GATEWAY, None)
+        }
+    };
+    let message = match &classified {
+        GptError::ApiError { message, .. } => message.clone(),
+        other => other.to_string(),
+    };
+    openai_error(status, &message, classified.kind(), code.as_deref())
+}
+
+fn is_loopback(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(
+        name.to_lowercase().as_str(),
+        "127.0.0.1" | "localhost" | "::1"
+    )
+}
+
+// A web page can't read the replies, but it could still post to the server and spend the
+// key: form-style posts are refused by content type, and pages on other sites by their
+// Origin, or by Host when a DNS name is rebound to 127.0.0.1.
+fn reject_foreign_request(headers: &HeaderMap) -> Option<Response<Body>> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if !header(HOST).is_some_and(is_loopback) {
+        return Some(openai_error(
+            StatusCode::FORBIDDEN,
+            "Requests must be sent to 127.0.0.1 or localhost",
+            "invalid_request_error",
+            None,
+        ));
+    }
+    if let Some(origin) = header(ORIGIN) {
+        let host = origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"));
+        if !host.is_some_and(is_loopback) {
+            return Some(openai_error(
+                StatusCode::FORBIDDEN,
+                &format!("Requests from {} are not allowed", origin),
+                "invalid_request_error",
+                None,
+            ));
+        }
+    }
+    let json = header(CONTENT_TYPE).is_some_and(|value| {
+        value
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if !json {
+        return Some(openai_error(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/json",
+            "invalid_request_error",
+            None,
+        ));
+    }
+    None
+}
+
+fn reply_usage(reply: &Value) -> ChatUsage {
+    let tokens = |key: &str| reply["usage"][key].as_u64().unwrap_or(0) as u32;
+    ChatUsage {
+        prompt_tokens: tokens("prompt_tokens"),
+        completion_tokens: tokens("completion_tokens"),
+        total_tokens: tokens("total_tokens"),
+    }
+}
+
+fn usage_entry(
+    ledger: &UsageLedger,
+    endpoint: Endpoint,
+    request: &Value,
+    reply: &Value,
+) -> UsageEntry {
+    let model = request["model"]
+        .as_str()
+        .or(reply["model"].as_str())
+        .unwrap_or_default();
+    match endpoint {
+        Endpoint::Images => ledger.image_entry(
+            // The API's own default when no model is given.
+            request["model"].as_str().unwrap_or("dall-e-2"),
+            reply["data"].as_array().map_or(0, Vec::len) as u32,
+            request["size"].as_str().unwrap_or("1024x1024"),
+            request["quality"].as_str().unwrap_or("standard"),
+        ),
+        Endpoint::Chat | Endpoint::Embeddings => ledger.chat_entry(model, &reply_usage(reply)),
+    }
+}
+
+async fn forward(
+    state: &ServeState,
+    endpoint: Endpoint,
+    url: &str,
+    body: &Value,
+    cache: Option<ResponseCache>,
+    key: &str,
+) -> Result<Response<Body>, Box<dyn Error>> {
+    let started = Instant::now();
+    let cached = cache.as_ref().and_then(|cache| cache.get(key)?.body);
+    let (text, status) = match cached {
+        Some(text) => (text, CacheStatus::Hit),
+        None => {
+            let request = async {
+                let response =
+                    post_openai_request(&state.client, url, body, &state.options).await?;
+                let text = response
+                    .text()
+                    .await
+                    .map_err(|e| GptError::network(format!("Failed to read the reply: {}", e)))?;
+                Ok::<_, Box<dyn Error>>(text)
+            };
+            let text =
+                with_timeout(TimeoutPhase::Total, state.options.timeouts.total, request).await??;
+            if let Some(cache) = &cache {
+                if let Err(e) = cache.store_body(key, url, &text) {
+                    print_warning(&format!("Failed to cache response: {}", e));
+                }
+            }
+            (text, cache_status(&cache))
+        }
+    };
+
+    let reply: Value = serde_json::from_str(&text).unwrap_or_default();
+    record_request(&state.options, status, started, |ledger| {
+        usage_entry(ledger, endpoint, body, &reply)
+    });
+    let mut response = Response::new(Body::from(text));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse()?);
+    response
+        .headers_mut()
+        .insert(CACHE_HEADER, cache_header(status).parse()?);
+    Ok(response)
+}
+
+// Streamed replies are passed through as they arrive, and read along the way so the usage
+// can be recorded and the reply cached once the stream ends.
+async fn forward_stream(
+    state: &ServeState,
+    url: &str,
+    body: &Value,
+    cache: Option<ResponseCache>,
+    key: String,
+) -> Result<Response<Body>, Box<dyn Error>> {
+    let started = Instant::now();
+    let cached = cache
+        .as_ref()
+        .and_then(|cache| cache.get(&key))
+        .filter(|cached| cached.stream.is_some());
+    let (upstream, status) = match cached {
+        Some(cached) => (cached.into_response()?, CacheStatus::Hit),
+        None => (
+            post_openai_request(&state.client, url, body, &state.options).await?,
+            cache_status(&cache),
+        ),
+    };
+
+    let model = body["model"].as_str().unwrap_or_default().to_string();
+    let messages: Vec<ChatMessageRole> =
+        serde_json::from_value(body["messages"].clone()).unwrap_or_default();
+    let (options, url) = (state.options.clone(), url.to_string());
+    let (mut sender, response_body) = Body::channel();
+    tokio::spawn(async move {
+        let mut stream = upstream.bytes_stream();
+        let mut buffer = Vec::new();
+        let mut result = ChatResult::default();
+        let mut complete = true;
+        while let Some(chunk) = stream.next().await {
+            let Ok(chunk) = chunk else {
+                complete = false;
+                break;
+            };
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let _ = parse_chat_line(&line, &mut result, &mut |_| Ok(()));
+            }
+            // The client hung up; stop reading so the upstream request is dropped too.
+            if sender.send_data(chunk).await.is_err() {
+                complete = false;
+                break;
+            }
+        }
+
+        let usage = chat_usage(result.usage.clone(), &result.content, &model, &messages);
+        record_request(&options, status, started, |ledger| {
+            ledger.chat_entry(&model, &usage)
+        });
+        if let (Some(cache), CacheStatus::Miss, true) = (&cache, status, complete) {
+            if let Err(e) = cache.store_stream(&key, &url, &result) {
+                print_warning(&format!("Failed to cache response: {}", e));
+            }
+        }
+    });
+
+    let mut response = Response::new(response_body);
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, "text/event-stream".parse()?);
+    headers.insert(CACHE_CONTROL, "no-cache".parse()?);
+    headers.insert(CACHE_HEADER, cache_header(status).parse()?);
+    Ok(response)
+}
+
+async fn route(
+    state: &ServeState,
+    request: Request<Body>,
+) -> Result<Response<Body>, Box<dyn Error>> {
+    let Some(endpoint) = Endpoint::from_path(request.uri().path()) else {
+        return Ok(openai_error(
+            StatusCode::NOT_FOUND,
+            &format!("Unknown endpoint {}", request.uri().path()),
+            "invalid_request_error",
+            None,
+        ));
+    };
+    if request.method() != Method::POST {
+        return Ok(openai_error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Only POST is supported",
+            "invalid_request_error",
+            None,
+        ));
+    }
+    if let Some(response) = reject_foreign_request(request.headers()) {
+        return Ok(response);
+    }
+    // Whatever key the client sent is dropped; requests go out with this server's key.
+    let bytes = hyper::body::to_bytes(request.into_body()).await?;
+    let mut body: Value = serde_json::from_slice(&bytes)
+        .map_err(|e| GptError::invalid_input(format!("Invalid JSON body: {}", e)))?;
+    redact::redact_request(&mut body, &state.options)?;
+    let model = body
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    enforce_budget(&state.options, &[model])?;
+
+    let url = format!("{}{}", state.upstream, endpoint.upstream_path());
+    let cache = ResponseCache::open(&state.options)?;
+    let key = cache_key(&url, &body);
+    if endpoint == Endpoint::Chat && body["stream"].as_bool() == Some(true) {
+        forward_stream(state, &url, &body, cache, key).await
+    } else {
+        forward(state, endpoint, &url, &body, cache, &key).await
+    }
+}
+
+async fn handle(
+    state: Arc<ServeState>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let started = Instant::now();
+    let line = format!("{} {}", request.method(), request.uri().path());
+    let response = match route(&state, request).await {
+        Ok(response) => response,
+        Err(e) => error_response(e.as_ref()),
+    };
+    eprintln!(
+        "{}",
+        format!(
+            "{} {} {:.2}s",
+            line,
+            response.status().as_u16(),
+            started.elapsed().as_secs_f64()
+        )
+        .dimmed()
+    );
+    Ok(response)
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    client: Client,
+    upstream: &str,
+    options: CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let state = Arc::new(ServeState {
+        client,
+        upstream: upstream.trim_end_matches('/').to_string(),
+        options,
+    });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request))) }
+    });
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| GptError::io(format!("Failed to start the server: {}", e)))?;
+    Server::from_tcp(listener)?.serve(make_service).await?;
+    Ok(())
+}
+
+pub fn upstream_base(options: &CliOptions, config: &Config) -> String {
+    options
+        .upstream
+        .clone()
+        .or_else(|| config.profile(&current_profile(options)).upstream)
+        .unwrap_or_else(|| OPENAI_API_BASE.to_string())
+}
+
+pub async fn process_serve_command(
+    client: &Client,
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    // Only listen on loopback: anyone who can connect gets to spend the API key.
+    let address = SocketAddr::from(([127, 0, 0, 1], options.port.unwrap_or(DEFAULT_SERVE_PORT)));
+    let listener = TcpListener::bind(address)
+        .map_err(|e| GptError::io(format!("Failed to listen on {}: {}", address, e)))?;
+    let address = listener.local_addr()?;
+    eprintln!(
+        "Serving the OpenAI API on {}; press Ctrl-C to stop",
+        format!("http://{}/v1", address).bold()
+    );
+    let upstream = upstream_base(options, &Config::load()?);
+    eprintln!("Forwarding requests to {}", upstream);
+    serve(listener, client.clone(), &upstream, options.clone()).await
+}
@@ -0,0 +1,367 @@
+use crate::constants::{
+    EXPLAIN_CMD_SYSTEM_PROMPT, GPT_API_URL, SH_SYSTEM_PROMPT, STRUCTURED_MODEL,
+};
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::output::{print_json, print_warning, OutputFormat};
+use crate::utils::{create_spinner, enforce_budget, run_structured_chat, structured_chat_request};
+use colored::Colorize;
+use regex::Regex;
+use reqwest::Client;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    process::Command,
+};
+
+// Checked locally as well, so --yes never depends only on the model flagging a command.
+const DANGEROUS_PATTERNS: &[&str] = &[
+    r"\b(?:rm|rmdir|unlink|shred|dd|mkfs(?:\.\w+)?|fdisk|parted|wipefs|truncate|mv)\b",
+    r"\b(?:sudo|doas|su|chmod|chown|chgrp|chattr|mount|umount)\b",
+    r"\b(?:kill|pkill|killall|reboot|shutdown|halt|poweroff|systemctl|crontab)\b",
+    r"\bgit\s+(?:push\b.*(?:\s-f\b|--force)|reset\s+--hard|clean\b|checkout\s+(?:--|\.)|branch\s+-D)",
+    r"\b(?:docker|podman|kubectl|helm|terraform)\b.*\b(?:rm|rmi|prune|delete|destroy|uninstall)\b",
+    r"\bsed\b[^|;&]*\s-i|\bperl\s+-\w*i|\s-delete\b",
+    r"\|\s*(?:sudo\s+)?(?:sh|bash|zsh|fish|python3?|perl|ruby|node)\b",
+    r"(?i)\b(?:drop|truncate)\s+(?:table|database|schema)\b",
+    r":\(\)\s*\{",
+];
+
+pub fn looks_dangerous(command: &str) -> bool {
+    if DANGEROUS_PATTERNS
+        .iter()
+        .any(|pattern| Regex::new(pattern).unwrap().is_match(command))
+    {
+        return true;
+    }
+    // A plain > overwrites its target; appends, fd duplication and /dev/null are fine.
+    Regex::new(r"(?:^|[^>])>\|?\s*([^\s&|;>]+)")
+        .unwrap()
+        .captures_iter(command)
+        .any(|captures| &captures[1] != "/dev/null")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShellChoice {
+    Run,
+    Edit,
+    Cancel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellContext {
+    pub shell: String,
+    pub os: String,
+}
+
+impl ShellContext {
+    pub fn detect() -> Self {
+        let shell = match env::var("SHELL") {
+            Ok(shell) if !shell.is_empty() => Path::new(&shell)
+                .file_name()
+                .map_or(shell.clone(), |name| name.to_string_lossy().into_owned()),
+            _ if cfg!(windows) && env::var_os("PSModulePath").is_some() => "powershell".to_string(),
+            _ if cfg!(windows) => "cmd".to_string(),
+            _ => "sh".to_string(),
+        };
+        let os = match env::consts::OS {
+            "macos" => "macOS",
+            "linux" => "Linux",
+            "windows" => "Windows",
+            "freebsd" => "FreeBSD",
+            other => other,
+        };
+        ShellContext {
+            shell,
+            os: os.to_string(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("The user's shell is {} on {}.", self.shell, self.os)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub command: String,
+    pub explanation: String,
+    pub dangerous: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandPart {
+    pub text: String,
+    pub explanation: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub summary: String,
+    pub parts: Vec<CommandPart>,
+}
+
+fn suggestion_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "command": { "type": "string" },
+            "explanation": { "type": "string" },
+            "dangerous": { "type": "boolean" }
+        },
+        "required": ["command", "explanation", "dangerous"],
+        "additionalProperties": false
+    })
+}
+
+fn explanation_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "summary": { "type": "string" },
+            "parts": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "text": { "type": "string" },
+                        "explanation": { "type": "string" }
+                    },
+                    "required": ["text", "explanation"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["summary", "parts"],
+        "additionalProperties": false
+    })
+}
+
+async fn ask_structured<T: DeserializeOwned>(
+    client: &Client,
+    api_url: &str,
+    system: &str,
+    prompt: String,
+    schema: (&str, Value),
+    options: &CliOptions,
+) -> Result<T, Box<dyn Error>> {
+    let model = options.model.as_deref().unwrap_or(STRUCTURED_MODEL);
+    let request = structured_chat_request(model, system, prompt, schema.0, schema.1);
+    enforce_budget(options, &[model])?;
+    let spinner = create_spinner("green", "Thinking...".to_string());
+    let reply = run_structured_chat(client, api_url, request, options).await;
+    spinner.finish_and_clear();
+    Ok(serde_json::from_value(reply?)?)
+}
+
+pub async fn suggest_command(
+    client: &Client,
+    api_url: &str,
+    request: &str,
+    context: &ShellContext,
+    options: &CliOptions,
+) -> Result<Suggestion, Box<dyn Error>> {
+    let system = format!("{} {}", SH_SYSTEM_PROMPT, context.describe());
+    let mut suggestion: Suggestion = ask_structured(
+        client,
+        api_url,
+        &system,
+        request.to_string(),
+        ("shell_command", suggestion_schema()),
+        options,
+    )
+    .await?;
+    if suggestion.command.trim().is_empty() {
+        return Err(GptError::invalid_reply("The model did not suggest a command").into());
+    }
+    suggestion.dangerous |= looks_dangerous(&suggestion.command);
+    Ok(suggestion)
+}
+
+pub async fn explain_command(
+    client: &Client,
+    api_url: &str,
+    command: &str,
+    context: &ShellContext,
+    options: &CliOptions,
+) -> Result<Explanation, Box<dyn Error>> {
+    let system = format!("{} {}", EXPLAIN_CMD_SYSTEM_PROMPT, context.describe());
+    ask_structured(
+        client,
+        api_url,
+        &system,
+        format!("Explain this command:\n\n{}", command),
+        ("command_explanation", explanation_schema()),
+        options,
+    )
+    .await
+}
+
+pub fn run_command(command: &str) -> Result<(), Box<dyn Error>> {
+    let mut process = if cfg!(windows) {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(command);
+        process
+    } else {
+        let shell = env::var("SHELL")
+            .ok()
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "sh".to_string());
+        let mut process = Command::new(shell);
+        process.arg("-c").arg(command);
+        process
+    };
+    let status = process
+        .status()
+        .map_err(|e| GptError::io(format!("Failed to run the command: {}", e)))?;
+    if !status.success() {
+        return Err(GptError::command_failed(format!("The command exited with {}", status)).into());
+    }
+    Ok(())
+}
+
+// Edits the command in $VISUAL or $EDITOR, since there is no line editor to prefill.
+fn edit_command(command: &str) -> Result<String, Box<dyn Error>> {
+    let mut file = tempfile::Builder::new()
+        .suffix(".sh")
+        .tempfile()
+        .map_err(|e| GptError::io(format!("Failed to create a temporary file: {}", e)))?;
+    writeln!(file, "{}", command)
+        .map_err(|e| GptError::io(format!("Failed to write the command: {}", e)))?;
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let status = Command::new(&editor)
+        .arg(file.path())
+        .status()
+        .map_err(|e| GptError::io(format!("Failed to run {}: {}", editor, e)))?;
+    if !status.success() {
+        return Err(GptError::command_failed(format!("{} exited with {}", editor, status)).into());
+    }
+    let edited = fs::read_to_string(file.path())
+        .map_err(|e| GptError::io(format!("Failed to read the command: {}", e)))?;
+    Ok(edited.trim().to_string())
+}
+
+fn ask() -> Result<ShellChoice, Box<dyn Error>> {
+    loop {
+        eprint!("{} ", "[r]un, [e]dit or [c]ancel?".bold());
+        let mut answer = String::new();
+        let read = io::stdin()
+            .lock()
+            .read_line(&mut answer)
+            .map_err(|e| GptError::io(format!("Failed to read stdin: {}", e)))?;
+        if read == 0 {
+            return Ok(ShellChoice::Cancel);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "r" | "run" | "y" | "yes" => return Ok(ShellChoice::Run),
+            "e" | "edit" => return Ok(ShellChoice::Edit),
+            "c" | "cancel" | "q" | "n" | "no" => return Ok(ShellChoice::Cancel),
+            _ => continue,
+        }
+    }
+}
+
+fn print_suggestion(suggestion: &Suggestion) {
+    eprintln!("\n  {}\n", suggestion.command.bold().green());
+    eprintln!("{}", suggestion.explanation);
+    if suggestion.dangerous {
+        print_warning("This command can change or delete data. Check it before running it.");
+    }
+    eprintln!();
+}
+
+pub async fn process_sh_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let request = args[2..].join(" ");
+    if request.trim().is_empty() {
+        return Err(GptError::invalid_input("Usage: gpt sh <what you want to do>").into());
+    }
+    let context = ShellContext::detect();
+    let suggestion = suggest_command(client, GPT_API_URL, &request, &context, options).await?;
+
+    if options.output != OutputFormat::Text {
+        return print_json(&suggestion, options.output);
+    }
+    if options.yes {
+        if suggestion.dangerous {
+            print_suggestion(&suggestion);
+            return Err(GptError::invalid_input(
+                "Not running a dangerous command without confirmation; drop --yes to review it",
+            )
+            .into());
+        }
+        return run_command(&suggestion.command);
+    }
+    // Without a terminal to confirm on, just print the command, e.g. for $(gpt sh ...).
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        if suggestion.dangerous {
+            print_warning("This command can change or delete data. Check it before running it.");
+        }
+        println!("{}", suggestion.command);
+        return Ok(());
+    }
+
+    print_suggestion(&suggestion);
+    match ask()? {
+        ShellChoice::Run => run_command(&suggestion.command),
+        ShellChoice::Edit => {
+            let command = edit_command(&suggestion.command)?;
+            if command.is_empty() {
+                return Ok(());
+            }
+            run_command(&command)
+        }
+        ShellChoice::Cancel => Ok(()),
+    }
+}
+
+pub async fn process_explain_cmd_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let command = args[2..].join(" ");
+    if command.trim().is_empty() {
+        return Err(GptError::invalid_input("Usage: gpt explain-cmd \"<command>\"").into());
+    }
+    let explanation = explain_command(
+        client,
+        GPT_API_URL,
+        &command,
+        &ShellContext::detect(),
+        options,
+    )
+    .await?;
+    if options.output != OutputFormat::Text {
+        return print_json(&explanation, options.output);
+    }
+
+    println!("{}\n", command.bold());
+    println!("{}\n", explanation.summary);
+    let width = explanation
+        .parts
+        .iter()
+        .map(|part| part.text.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(30);
+    for part in &explanation.parts {
+        println!(
+            "  {}  {}",
+            format!("{:<width$}", part.text, width = width).yellow(),
+            part.explanation
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use crate::chat::{split_params, ChatMessageRole, OpenAiChatRequestBody, StreamOptions};
+use crate::constants::{
+    RequestType, DEFAULT_MODEL, DEFAULT_SYSTEM_PROMPT, GPT_API_URL, TEMPLATE_SCAFFOLD,
+};
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::output::{print_json, OutputFormat};
+use crate::utils::{enforce_budget, make_openai_request, prepare_chat_request};
+use colored::Colorize;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+pub const TEMPLATE_EXTENSIONS: &[&str] = &["toml", "md"];
+const FRONT_MATTER_DELIMITER: &str = "+++";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Template {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub params: Map<String, Value>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Template {
+    // Markdown templates keep their settings in TOML front matter between +++ lines, and
+    // the body is the user prompt.
+    pub fn parse(contents: &str, markdown: bool) -> Result<Self, String> {
+        if !markdown {
+            return toml::from_str(contents).map_err(|e| e.to_string());
+        }
+        let Some(rest) = contents.strip_prefix(FRONT_MATTER_DELIMITER) else {
+            return Ok(Template {
+                prompt: contents.trim().to_string(),
+                ..Template::default()
+            });
+        };
+        let (front_matter, body) = rest
+            .split_once(&format!("\n{}", FRONT_MATTER_DELIMITER))
+            .ok_or("front matter is missing its closing +++")?;
+        let mut template: Template = toml::from_str(front_matter).map_err(|e| e.to_string())?;
+        if !template.prompt.is_empty() {
+            return Err(
+                "markdown templates take the prompt from the body, not front matter".into(),
+            );
+        }
+        template.prompt = body.trim().to_string();
+        Ok(template)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| GptError::io(format!("Failed to read {}: {}", path.display(), e)))?;
+        let markdown = path.extension().is_some_and(|ext| ext == "md");
+        Self::parse(&contents, markdown).map_err(|e| {
+            GptError::invalid_input(format!("Invalid template {}: {}", path.display(), e)).into()
+        })
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn template_in_dir(dir: &Path, name: &str) -> Option<PathBuf> {
+    TEMPLATE_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", name, ext)))
+        .find(|path| path.is_file())
+}
+
+pub fn find_template(dir: &Path, name: &str) -> Result<PathBuf, GptError> {
+    if valid_name(name) {
+        if let Some(path) = template_in_dir(dir, name) {
+            return Ok(path);
+        }
+    }
+    let path = Path::new(name);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    Err(GptError::invalid_input(format!(
+        "Template {} not found in {} (see `gpt templates list`)",
+        name,
+        dir.display()
+    )))
+}
+
+pub fn list_templates(dir: &Path) -> Result<Vec<(String, PathBuf)>, Box<dyn Error>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(GptError::io(format!("Failed to read {}: {}", dir.display(), e)).into())
+        }
+    };
+    let mut templates: Vec<(String, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| TEMPLATE_EXTENSIONS.contains(&ext))
+        })
+        .filter_map(|path| Some((path.file_stem()?.to_str()?.to_string(), path)))
+        .collect();
+    templates.sort();
+    Ok(templates)
+}
+
+pub fn parse_var(value: &str) -> Result<(String, String), GptError> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(GptError::invalid_input(format!(
+            "Invalid value for --var: {} (expected key=value)",
+            value
+        ))),
+    }
+}
+
+// Fills {{var}}, {{stdin}} and {{file:path}} placeholders; stdin is only read if it is used.
+pub fn render(
+    text: &str,
+    vars: &HashMap<String, String>,
+    stdin: &mut dyn FnMut() -> Result<String, Box<dyn Error>>,
+) -> Result<String, Box<dyn Error>> {
+    let mut rendered = String::with_capacity(text.len());
+    let mut missing = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let key = rest[start + 2..start + end].trim();
+        match (key, key.strip_prefix("file:")) {
+            ("stdin", _) => rendered.push_str(&stdin()?),
+            (_, Some(path)) => {
+                let path = path.trim();
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| GptError::io(format!("Failed to read {}: {}", path, e)))?;
+                rendered.push_str(&contents);
+            }
+            _ => match vars.get(key) {
+                Some(value) => rendered.push_str(value),
+                None => missing.push(key.to_string()),
+            },
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+        return Err(GptError::invalid_input(format!(
+            "Missing template variable(s): {} (pass them with --var key=value)",
+            missing.join(", ")
+        ))
+        .into());
+    }
+    Ok(rendered)
+}
+
+pub fn build_template_request(
+    template: &Template,
+    extra: &[String],
+    options: &CliOptions,
+    stdin: &mut dyn FnMut() -> Result<String, Box<dyn Error>>,
+) -> Result<OpenAiChatRequestBody, Box<dyn Error>> {
+    let mut vars: HashMap<String, String> = template.vars.clone().into_iter().collect();
+    vars.extend(options.vars.iter().cloned());
+
+    let mut prompt = render(&template.prompt, &vars, stdin)?;
+    if !extra.is_empty() {
+        prompt = format!("{}\n\n{}", prompt.trim_end(), extra.join(" "));
+    }
+    let system = match &template.system {
+        Some(system) => render(system, &vars, stdin)?,
+        None => DEFAULT_SYSTEM_PROMPT.to_string(),
+    };
+    let (max_tokens, params) = split_params(template.params.clone())
+        .map_err(|e| GptError::invalid_input(format!("Invalid template params: {}", e)))?;
+    let model = options
+        .model
+        .clone()
+        .or_else(|| template.model.clone())
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+
+    Ok(OpenAiChatRequestBody {
+        model,
+        messages: vec![
+            ChatMessageRole {
+                role: "system".to_string(),
+                content: system,
+            },
+            ChatMessageRole {
+                role: "user".to_string(),
+                content: prompt,
+            },
+        ],
+        stream: true,
+        max_tokens,
+        stream_options: Some(StreamOptions {
+            include_usage: true,
+        }),
+        response_format: None,
+        params,
+    })
+}
+
+fn templates_dir(options: &CliOptions) -> Result<&Path, GptError> {
+    options
+        .templates_dir
+        .as_deref()
+        .ok_or_else(|| GptError::invalid_input("No template directory configured"))
+}
+
+fn read_stdin() -> Result<String, Box<dyn Error>> {
+    let mut text = String::new();
+    io::stdin()
+        .read_to_string(&mut text)
+        .map_err(|e| GptError::io(format!("Failed to read stdin: {}", e)))?;
+    Ok(text)
+}
+
+pub async fn process_run_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let name = args.get(2).ok_or_else(|| {
+        GptError::invalid_input("Usage: gpt run <template> [--var key=value] [extra prompt]")
+    })?;
+    let template = Template::load(&find_template(templates_dir(options)?, name)?)?;
+
+    let mut stdin = None;
+    let mut read_once = || -> Result<String, Box<dyn Error>> {
+        if stdin.is_none() {
+            stdin = Some(read_stdin()?);
+        }
+        Ok(stdin.clone().unwrap_or_default())
+    };
+    let mut request = build_template_request(&template, &args[3..], options, &mut read_once)?;
+    prepare_chat_request(&mut request, options)?;
+    enforce_budget(options, &[&request.model])?;
+    make_openai_request(client, RequestType::Chat(request), GPT_API_URL, options).await
+}
+
+pub fn process_templates_command(
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let dir = templates_dir(options)?;
+    let usage = "Usage: gpt templates list|show <name>|new <name>";
+    match (args.get(2).map(String::as_str), args.get(3)) {
+        (Some("list") | None, _) => {
+            let mut listed = Vec::new();
+            for (name, path) in list_templates(dir)? {
+                let description = Template::load(&path)
+                    .map(|template| template.description.unwrap_or_default())
+                    .unwrap_or_else(|e| format!("({})", e));
+                listed.push(json!({ "name": name, "description": description, "path": path }));
+            }
+            if options.output != OutputFormat::Text {
+                return print_json(&listed, options.output);
+            }
+            if listed.is_empty() {
+                println!(
+                    "No templates in {}. Create one with `gpt templates new <name>`.",
+                    dir.display()
+                );
+            }
+            for template in &listed {
+                println!(
+                    "{:<20} {}",
+                    template["name"].as_str().unwrap_or_default().bold(),
+                    template["description"].as_str().unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        (Some("show"), Some(name)) => {
+            let path = find_template(dir, name)?;
+            if options.output != OutputFormat::Text {
+                return print_json(&Template::load(&path)?, options.output);
+            }
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| GptError::io(format!("Failed to read {}: {}", path.display(), e)))?;
+            print!("{}", contents);
+            Ok(())
+        }
+        (Some("new"), Some(name)) => {
+            if !valid_name(name) {
+                return Err(GptError::invalid_input(format!(
+                    "Invalid template name: {} (use letters, digits, - and _)",
+                    name
+                ))
+                .into());
+            }
+            if let Some(path) = template_in_dir(dir, name) {
+                return Err(GptError::invalid_input(format!(
+                    "Template {} already exists at {}",
+                    name,
+                    path.display()
+                ))
+                .into());
+            }
+            let path = dir.join(format!("{}.toml", name));
+            fs::create_dir_all(dir)
+                .and_then(|_| fs::write(&path, TEMPLATE_SCAFFOLD))
+                .map_err(|e| GptError::io(format!("Failed to write {}: {}", path.display(), e)))?;
+            println!("{}", path.display());
+            Ok(())
+        }
+        _ => Err(GptError::invalid_input(usage).into()),
+    }
+}
@@ -0,0 +1,82 @@
+use crate::constants::{
+    DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_FIRST_BYTE_TIMEOUT_SECS, DEFAULT_IDLE_TIMEOUT_SECS,
+    DEFAULT_TOTAL_TIMEOUT_SECS,
+};
+use std::{fmt, future::Future, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub first_byte: Duration,
+    pub idle: Duration,
+    pub total: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            first_byte: Duration::from_secs(DEFAULT_FIRST_BYTE_TIMEOUT_SECS),
+            idle: Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+            total: Duration::from_secs(DEFAULT_TOTAL_TIMEOUT_SECS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    Connect,
+    FirstByte,
+    Idle,
+    Total,
+}
+
+impl TimeoutPhase {
+    fn description(self) -> &'static str {
+        match self {
+            TimeoutPhase::Connect => "connecting to the server",
+            TimeoutPhase::FirstByte => "waiting for the first byte of the response",
+            TimeoutPhase::Idle => "waiting for the next chunk of the response",
+            TimeoutPhase::Total => "waiting for the complete response",
+        }
+    }
+
+    fn flag(self) -> &'static str {
+        match self {
+            TimeoutPhase::Connect => "--connect-timeout",
+            TimeoutPhase::FirstByte => "--first-byte-timeout",
+            TimeoutPhase::Idle => "--idle-timeout",
+            TimeoutPhase::Total => "--total-timeout",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeoutError {
+    pub phase: TimeoutPhase,
+    pub after: Duration,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {}s {} (adjust with {})",
+            self.after.as_secs_f64(),
+            self.phase.description(),
+            self.phase.flag()
+        )
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+pub async fn with_timeout<F: Future>(
+    phase: TimeoutPhase,
+    after: Duration,
+    future: F,
+) -> Result<F::Output, TimeoutError> {
+    tokio::time::timeout(after, future)
+        .await
+        .map_err(|_| TimeoutError { phase, after })
+}
@@ -0,0 +1,97 @@
+use crate::chat::ChatMessageRole;
+use serde::Serialize;
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton};
+
+// Every message is wrapped as <|start|>{role}\n{content}<|end|>\n, and the reply is primed
+// with <|start|>assistant<|message|>.
+const TOKENS_PER_MESSAGE: usize = 3;
+const REPLY_PRIMING_TOKENS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Cl100kBase,
+    O200kBase,
+}
+
+impl Encoding {
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_lowercase();
+        if ["gpt-4o", "gpt-4.1", "gpt-5", "chatgpt-4o", "o1", "o3", "o4"]
+            .iter()
+            .any(|prefix| model.starts_with(prefix))
+        {
+            Encoding::O200kBase
+        } else {
+            Encoding::Cl100kBase
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Cl100kBase => "cl100k_base",
+            Encoding::O200kBase => "o200k_base",
+        }
+    }
+
+    pub fn count(self, text: &str) -> usize {
+        match self {
+            Encoding::Cl100kBase => cl100k_base_singleton().lock().encode_ordinary(text).len(),
+            Encoding::O200kBase => o200k_base_singleton().lock().encode_ordinary(text).len(),
+        }
+    }
+}
+
+pub fn context_window(model: &str) -> Option<usize> {
+    let model = model.to_lowercase();
+    let window = match model.as_str() {
+        m if m.starts_with("gpt-4.1") => 1_047_576,
+        m if m.starts_with("gpt-5") => 400_000,
+        m if m.starts_with("o1-mini") => 128_000,
+        m if m.starts_with("o1") || m.starts_with("o3") || m.starts_with("o4") => 200_000,
+        m if m.starts_with("gpt-4o") || m.starts_with("chatgpt-4o") => 128_000,
+        m if m.starts_with("gpt-4-turbo")
+            || m.starts_with("gpt-4-1106")
+            || m.starts_with("gpt-4-0125")
+            || m.starts_with("gpt-4-vision") =>
+        {
+            128_000
+        }
+        m if m.starts_with("gpt-4-32k") => 32_768,
+        m if m.starts_with("gpt-4") => 8_192,
+        m if m.starts_with("gpt-3.5-turbo-instruct") => 4_096,
+        m if m.starts_with("gpt-3.5-turbo") => 16_385,
+        _ => return None,
+    };
+    Some(window)
+}
+
+pub fn count_message_tokens(messages: &[ChatMessageRole], model: &str) -> usize {
+    let encoding = Encoding::for_model(model);
+    messages
+        .iter()
+        .map(|message| {
+            TOKENS_PER_MESSAGE + encoding.count(&message.role) + encoding.count(&message.content)
+        })
+        .sum::<usize>()
+        + REPLY_PRIMING_TOKENS
+}
+
+pub fn trim_history(messages: &mut Vec<ChatMessageRole>, model: &str, budget: usize) -> usize {
+    let mut dropped = 0;
+    while count_message_tokens(messages, model) > budget {
+        // The latest message is the one being answered, so it is never dropped.
+        let oldest = messages
+            .iter()
+            .take(messages.len().saturating_sub(1))
+            .position(|message| message.role != "system");
+        match oldest {
+            Some(index) => {
+                messages.remove(index);
+                dropped += 1;
+            }
+            None => break,
+        }
+    }
+    dropped
+}
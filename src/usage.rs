@@ -0,0 +1,425 @@
+use crate::chat::ChatUsage;
+use crate::config::{config_dir, Config, TokenPrice};
+use crate::error::GptError;
+use crate::options::CliOptions;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const LEDGER_FILE: &str = "usage.jsonl";
+pub const DEFAULT_PROFILE: &str = "default";
+
+// USD per million prompt and completion tokens; the most specific prefix wins.
+const TOKEN_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-4-32k", 60.0, 120.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4-1106", 10.0, 30.0),
+    ("gpt-4-0125", 10.0, 30.0),
+    ("gpt-4-vision", 10.0, 30.0),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o-2024-05-13", 5.0, 15.0),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-5", 1.25, 10.0),
+    ("gpt-5-mini", 0.25, 2.0),
+    ("gpt-5-nano", 0.05, 0.4),
+    ("chatgpt-4o", 5.0, 15.0),
+    ("o1", 15.0, 60.0),
+    ("o1-mini", 1.1, 4.4),
+    ("o3", 2.0, 8.0),
+    ("o3-mini", 1.1, 4.4),
+    ("o4-mini", 1.1, 4.4),
+    ("text-embedding-3-small", 0.02, 0.0),
+    ("text-embedding-3-large", 0.13, 0.0),
+    ("text-embedding-ada-002", 0.1, 0.0),
+];
+
+// USD per image, keyed by "<quality> <size>".
+const IMAGE_PRICES: &[(&str, &str, f64)] = &[
+    ("dall-e-2", "standard 256x256", 0.016),
+    ("dall-e-2", "standard 512x512", 0.018),
+    ("dall-e-2", "standard 1024x1024", 0.02),
+    ("dall-e-3", "standard 1024x1024", 0.04),
+    ("dall-e-3", "standard 1024x1792", 0.08),
+    ("dall-e-3", "standard 1792x1024", 0.08),
+    ("dall-e-3", "hd 1024x1024", 0.08),
+    ("dall-e-3", "hd 1024x1792", 0.12),
+    ("dall-e-3", "hd 1792x1024", 0.12),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct UsageEntry {
+    pub timestamp: u64,
+    pub profile: String,
+    pub model: String,
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: u32,
+    #[serde(default)]
+    pub images: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    pub cost: f64,
+    // Set for results that can be downloaded more than once, like async batch lines, so
+    // they are only counted the first time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UsageGroup {
+    #[default]
+    Model,
+    Profile,
+    Day,
+}
+
+impl UsageGroup {
+    pub fn name(self) -> &'static str {
+        match self {
+            UsageGroup::Model => "model",
+            UsageGroup::Profile => "profile",
+            UsageGroup::Day => "day",
+        }
+    }
+}
+
+impl FromStr for UsageGroup {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "model" => Ok(UsageGroup::Model),
+            "profile" => Ok(UsageGroup::Profile),
+            "day" => Ok(UsageGroup::Day),
+            _ => Err(format!(
+                "Invalid usage grouping: {} (expected model, profile or day)",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct UsageRow {
+    pub key: String,
+    pub requests: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub images: u32,
+    pub cost: f64,
+}
+
+impl UsageRow {
+    fn add(&mut self, entry: &UsageEntry) {
+        self.requests += 1;
+        self.prompt_tokens += u64::from(entry.prompt_tokens);
+        self.completion_tokens += u64::from(entry.completion_tokens);
+        self.images += entry.images;
+        self.cost += entry.cost;
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn longest_prefix<'a, T>(
+    model: &str,
+    candidates: impl Iterator<Item = (&'a str, T)>,
+) -> Option<(&'a str, T)> {
+    candidates
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+}
+
+pub fn token_price(config: &Config, model: &str) -> Option<TokenPrice> {
+    let configured = config
+        .prices
+        .iter()
+        .map(|(prefix, price)| (prefix.as_str(), *price));
+    longest_prefix(model, configured)
+        .or_else(|| {
+            let defaults = TOKEN_PRICES
+                .iter()
+                .map(|&(prefix, prompt, completion)| (prefix, TokenPrice { prompt, completion }));
+            longest_prefix(model, defaults)
+        })
+        .map(|(_, price)| price)
+}
+
+pub fn chat_cost(config: &Config, model: &str, usage: &ChatUsage) -> f64 {
+    token_price(config, model).map_or(0.0, |price| {
+        (f64::from(usage.prompt_tokens) * price.prompt
+            + f64::from(usage.completion_tokens) * price.completion)
+            / 1_000_000.0
+    })
+}
+
+pub fn has_price(config: &Config, model: &str) -> bool {
+    token_price(config, model).is_some()
+        || config.image_prices.contains_key(model)
+        || IMAGE_PRICES.iter().any(|&(m, _, _)| m == model)
+}
+
+pub fn image_price(config: &Config, model: &str, quality: &str, size: &str) -> Option<f64> {
+    let key = format!("{} {}", quality, size);
+    config
+        .image_prices
+        .get(model)
+        .and_then(|prices| prices.get(&key).copied())
+        .or_else(|| {
+            IMAGE_PRICES
+                .iter()
+                .find(|&&(m, k, _)| m == model && k == key)
+                .map(|&(_, _, price)| price)
+        })
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+pub fn format_day(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub fn month_start(timestamp: u64) -> u64 {
+    let days = (timestamp / 86_400) as i64;
+    let (_, _, day) = civil_from_days(days);
+    (days - i64::from(day) + 1) as u64 * 86_400
+}
+
+pub fn parse_period(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let amount: u64 = value[..split].parse().ok()?;
+    let unit = match &value[split..] {
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount * unit))
+}
+
+pub fn summarize(entries: &[UsageEntry], since: u64, by: UsageGroup) -> Vec<UsageRow> {
+    let mut groups: BTreeMap<String, UsageRow> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| entry.timestamp >= since) {
+        let key = match by {
+            UsageGroup::Model => entry.model.clone(),
+            UsageGroup::Profile => entry.profile.clone(),
+            UsageGroup::Day => format_day(entry.timestamp),
+        };
+        groups
+            .entry(key.clone())
+            .or_insert_with(|| UsageRow {
+                key,
+                ..UsageRow::default()
+            })
+            .add(entry);
+    }
+    let mut rows: Vec<UsageRow> = groups.into_values().collect();
+    if by != UsageGroup::Day {
+        rows.sort_by(|a, b| b.cost.total_cmp(&a.cost));
+    }
+    rows
+}
+
+pub fn total(rows: &[UsageRow]) -> UsageRow {
+    let mut total = UsageRow {
+        key: "total".to_string(),
+        ..UsageRow::default()
+    };
+    for row in rows {
+        total.requests += row.requests;
+        total.prompt_tokens += row.prompt_tokens;
+        total.completion_tokens += row.completion_tokens;
+        total.images += row.images;
+        total.cost += row.cost;
+    }
+    total
+}
+
+pub fn current_profile(options: &CliOptions) -> String {
+    options
+        .profile
+        .clone()
+        .or_else(|| env::var("GPT_PROFILE").ok())
+        .or_else(|| env::var("USER").ok())
+        .or_else(|| env::var("USERNAME").ok())
+        .filter(|profile| !profile.is_empty())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+pub fn ledger_path() -> PathBuf {
+    config_dir().join(LEDGER_FILE)
+}
+
+pub struct UsageLedger {
+    pub path: Option<PathBuf>,
+    pub config: Config,
+    pub profile: String,
+}
+
+impl UsageLedger {
+    pub fn open(options: &CliOptions) -> Result<Self, Box<dyn Error>> {
+        Ok(UsageLedger {
+            path: options.usage_ledger.clone(),
+            config: Config::load()?,
+            profile: current_profile(options),
+        })
+    }
+
+    pub fn entries(&self) -> Result<Vec<UsageEntry>, Box<dyn Error>> {
+        match &self.path {
+            Some(path) => read_ledger(path),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn chat_entry(&self, model: &str, usage: &ChatUsage) -> UsageEntry {
+        let cost = chat_cost(&self.config, model, usage);
+        UsageEntry {
+            timestamp: now(),
+            profile: self.profile.clone(),
+            model: model.to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost,
+            ..UsageEntry::default()
+        }
+    }
+
+    pub fn image_entry(&self, model: &str, images: u32, size: &str, quality: &str) -> UsageEntry {
+        let cost = image_price(&self.config, model, quality, size).unwrap_or(0.0);
+        UsageEntry {
+            timestamp: now(),
+            profile: self.profile.clone(),
+            model: model.to_string(),
+            images,
+            size: Some(size.to_string()),
+            quality: Some(quality.to_string()),
+            cost: cost * f64::from(images),
+            ..UsageEntry::default()
+        }
+    }
+
+    pub fn record(&self, entry: &UsageEntry) -> Result<(), Box<dyn Error>> {
+        match &self.path {
+            Some(path) => append_entry(path, entry),
+            None => Ok(()),
+        }
+    }
+
+    // Requests to a model without a price are recorded at $0, so no budget or cap can stop them.
+    pub fn check_priced(&self, budget: Option<f64>, model: &str) -> Result<(), GptError> {
+        let limited = self.config.monthly_cap.is_some()
+            || budget
+                .or(self.config.profile(&self.profile).budget)
+                .is_some();
+        if !limited || has_price(&self.config, model) {
+            return Ok(());
+        }
+        Err(GptError::invalid_input(format!(
+            "{} has no known price, so the budget can't apply to it; add it to \"prices\" in config.json",
+            model
+        )))
+    }
+
+    pub fn check_budget(&self, budget: Option<f64>, now: u64) -> Result<(), GptError> {
+        let since = month_start(now);
+        let entries = self.entries().map_err(|e| GptError::io(e.to_string()))?;
+        let spent = |profile: Option<&str>| -> f64 {
+            entries
+                .iter()
+                .filter(|entry| entry.timestamp >= since)
+                .filter(|entry| profile.is_none_or(|p| entry.profile == p))
+                .map(|entry| entry.cost)
+                .sum()
+        };
+
+        if let Some(cap) = self.config.monthly_cap {
+            let spent = spent(None);
+            if spent >= cap {
+                return Err(GptError::BudgetExceeded {
+                    message: format!(
+                        "Monthly cap of ${:.2} reached (${:.2} spent since {})",
+                        cap,
+                        spent,
+                        format_day(since)
+                    ),
+                });
+            }
+        }
+        if let Some(budget) = budget.or(self.config.profile(&self.profile).budget) {
+            let spent = spent(Some(&self.profile));
+            if spent >= budget {
+                return Err(GptError::BudgetExceeded {
+                    message: format!(
+                        "Monthly budget of ${:.2} for profile {} reached (${:.2} spent since {})",
+                        budget,
+                        self.profile,
+                        spent,
+                        format_day(since)
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn append_entry(path: &Path, entry: &UsageEntry) -> Result<(), Box<dyn Error>> {
+    let write = || -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)
+    };
+    write().map_err(|e| GptError::io(format!("Failed to write {}: {}", path.display(), e)).into())
+}
+
+pub fn read_ledger(path: &Path) -> Result<Vec<UsageEntry>, Box<dyn Error>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(GptError::io(format!("Failed to read {}: {}", path.display(), e)).into())
+        }
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
@@ -1,24 +1,64 @@
-use crate::chat::{ChatApiResponse, ChatMessageRole, OpenAiChatRequestBody};
+use crate::batch;
+use crate::cache::{cache_key, CacheStatus, CachedResponse, ResponseCache};
+use crate::chat::{
+    ChatApiResponse, ChatCompletionResponse, ChatMessageRole, ChatResult, ChatUsage,
+    JsonSchemaFormat, OpenAiChatRequestBody, ResponseFormat, StreamOptions,
+};
+use crate::code_blocks::{extract_code_blocks, save_code_blocks, select_code_blocks, CodeBlock};
+use crate::commit;
+use crate::compare;
+use crate::completions;
 use crate::constants::{
-    RequestType, CMD_DALLE, CMD_GPT4, CMD_VISION, DALLE_API_URL, DALLE_MODEL, DEFAULT_MODEL,
-    DEFAULT_VISION_INSTRUCTIONS, GPT4_MODEL, GPT4_VISION_MODEL, GPT_API_URL,
+    RequestType, CMD_BATCH, CMD_COMMIT, CMD_COMPARE, CMD_COMPLETIONS, CMD_DALLE, CMD_EDIT,
+    CMD_EVAL, CMD_EXPLAIN_CMD, CMD_GPT4, CMD_REVIEW, CMD_RUN, CMD_SERVE, CMD_SH, CMD_SHELL_INIT,
+    CMD_TEMPLATES, CMD_TOKENS, CMD_USAGE, CMD_VISION, CMD_WATCH, DALLE_API_URL, DALLE_MODEL,
+    DEFAULT_MODEL, DEFAULT_SYSTEM_PROMPT, DEFAULT_VISION_INSTRUCTIONS, GPT4_MODEL,
+    GPT4_STRUCTURED_MODEL, GPT4_VISION_MODEL, GPT_API_URL, LOCAL_API_KEY_ENV, MIN_REPLY_TOKENS,
+    STRUCTURED_MODEL,
 };
+use crate::edit;
+use crate::error::{with_context, GptError};
+use crate::eval;
 use crate::images::{DalleApiResponse, OpenAiDalleRequestBody};
+use crate::interrupt;
+use crate::options::CliOptions;
+use crate::output::{
+    markdown_enabled, print_json, print_stats, print_warning, render_markdown, OutputFormat,
+    RequestStats, StreamPrinter,
+};
+use crate::redact;
+use crate::retry::{
+    is_retryable_error, is_retryable_status, rate_limit_delay, retry_after_delay, RetryPolicy,
+    StreamInterrupted,
+};
+use crate::review;
+use crate::schema;
+use crate::serve;
+use crate::shell;
+use crate::templates;
+use crate::timeouts::{with_timeout, TimeoutError, TimeoutPhase};
+use crate::tokens::{self, Encoding};
+use crate::usage::{self, UsageEntry, UsageLedger};
 use crate::vision::{
     ImageUrl, OpenAiVisionRequestBody, VisionApiResponse, VisionContent, VisionMessageRole,
 };
-use base64;
+use crate::watch;
+use colored::Colorize;
 use futures::stream::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest::{
     header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
-    Client,
+    Client, StatusCode,
 };
+use serde::Serialize;
+use serde_json::{json, Map, Value};
 use std::{
     env,
     error::Error,
     fs,
-    io::{self, Read, Write},
+    io::{self, IsTerminal, Read},
+    path::Path,
+    time::{Duration, Instant},
 };
 
 pub fn build_headers() -> Result<HeaderMap, Box<dyn Error>> {
@@ -27,14 +67,26 @@ pub fn build_headers() -> Result<HeaderMap, Box<dyn Error>> {
         AUTHORIZATION,
         HeaderValue::from_str(&format!(
             "Bearer {}",
-            env::var("OPENAI_API_KEY")
-                .map_err(|_| "OPENAI_API_KEY environment variable not found or invalid")?
+            env::var("OPENAI_API_KEY").map_err(|_| GptError::MissingApiKey)?
         ))?,
     );
     headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
     Ok(headers)
 }
 
+// Local OpenAI-compatible servers don't get the OpenAI key, only their own if one is set.
+pub fn local_headers() -> Result<HeaderMap, Box<dyn Error>> {
+    let mut headers = HeaderMap::new();
+    if let Ok(key) = env::var(LOCAL_API_KEY_ENV) {
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", key))?,
+        );
+    }
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(headers)
+}
+
 pub fn create_spinner(color: &str, message: String) -> ProgressBar {
     let spinner = ProgressBar::new_spinner();
     spinner.set_style(
@@ -44,20 +96,17 @@ pub fn create_spinner(color: &str, message: String) -> ProgressBar {
     );
     spinner.enable_steady_tick(100);
     spinner.set_message(message);
+    interrupt::track_spinner(&spinner);
 
     spinner
 }
 
 pub async fn encode_image(image_path: &str) -> Result<String, Box<dyn Error>> {
     let mut file = fs::File::open(image_path)
-        .map_err(|_| format!("Failed to open image file: {}", image_path))?;
+        .map_err(|_| GptError::io(format!("Failed to open image file: {}", image_path)))?;
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).map_err(|_| {
-        Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to read image file",
-        ))
-    })?;
+    file.read_to_end(&mut buffer)
+        .map_err(|_| GptError::io("Failed to read image file"))?;
     Ok(base64::encode(buffer))
 }
 
@@ -71,7 +120,7 @@ pub async fn build_vision_request(
     };
     let image_base64 = encode_image(&args[2])
         .await
-        .map_err(|e| format!("Failed to encode image: {}", e))?;
+        .map_err(|e| with_context(e, "Failed to encode image"))?;
     Ok(OpenAiVisionRequestBody {
         model: GPT4_VISION_MODEL.to_string(),
         messages: vec![VisionMessageRole {
@@ -90,20 +139,77 @@ pub async fn build_vision_request(
 }
 
 pub fn build_chat_request(args: &[String], model: &str) -> OpenAiChatRequestBody {
+    chat_request(model, DEFAULT_SYSTEM_PROMPT, args[2..].join(" "))
+}
+
+pub fn chat_request(model: &str, system: &str, prompt: String) -> OpenAiChatRequestBody {
     OpenAiChatRequestBody {
         model: model.to_string(),
         messages: vec![
             ChatMessageRole {
                 role: "system".to_string(),
-                content: "You are a helpful assistant.".to_string(),
+                content: system.to_string(),
             },
             ChatMessageRole {
                 role: "user".to_string(),
-                content: args[2..].join(" "),
+                content: prompt,
             },
         ],
         stream: true,
+        max_tokens: None,
+        stream_options: None,
+        response_format: None,
+        params: Map::new(),
+    }
+}
+
+pub fn prepare_chat_request(
+    request: &mut OpenAiChatRequestBody,
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(max_tokens) = options.max_tokens {
+        request.max_tokens = Some(max_tokens);
+    }
+    let Some(window) = tokens::context_window(&request.model) else {
+        return Ok(());
+    };
+    let reserved = request.max_tokens.unwrap_or(0) as usize;
+
+    let dropped = tokens::trim_history(
+        &mut request.messages,
+        &request.model,
+        window.saturating_sub(reserved),
+    );
+    if dropped > 0 {
+        print_warning(&format!(
+            "Dropped {} oldest message(s) to fit the {} token context window of {}",
+            dropped, window, request.model
+        ));
     }
+
+    let prompt_tokens = tokens::count_message_tokens(&request.messages, &request.model);
+    if prompt_tokens + reserved > window {
+        let requested = match request.max_tokens {
+            Some(max_tokens) => format!(" plus {} reply tokens", max_tokens),
+            None => String::new(),
+        };
+        return Err(GptError::ContextLengthExceeded {
+            message: format!(
+                "Prompt is {} tokens{}, which exceeds the {} token context window of {}",
+                prompt_tokens, requested, window, request.model
+            ),
+        }
+        .into());
+    }
+    if request.max_tokens.is_none() && window - prompt_tokens < MIN_REPLY_TOKENS {
+        print_warning(&format!(
+            "Prompt is {} tokens, leaving only {} tokens for the reply from {}",
+            prompt_tokens,
+            window - prompt_tokens,
+            request.model
+        ));
+    }
+    Ok(())
 }
 
 pub fn build_dalle_request(args: &[String]) -> OpenAiDalleRequestBody {
@@ -116,63 +222,299 @@ pub fn build_dalle_request(args: &[String]) -> OpenAiDalleRequestBody {
     }
 }
 
-pub async fn process_vision_response(response: reqwest::Response) -> Result<(), Box<dyn Error>> {
+pub async fn process_vision_response(
+    response: reqwest::Response,
+    options: &CliOptions,
+) -> Result<ChatResult, Box<dyn Error>> {
     let api_response = response.json::<VisionApiResponse>().await?;
-    println!(
-        "{}",
-        api_response
-            .choices
-            .get(0)
-            .map_or("No content in response", |c| &c.message.content)
-    );
+    let choice = api_response.choices.into_iter().next();
+    let result = ChatResult {
+        content: choice
+            .as_ref()
+            .map_or_else(String::new, |c| c.message.content.clone()),
+        model: api_response.model,
+        finish_reason: choice.and_then(|c| c.finish_reason),
+        usage: api_response.usage,
+        ..ChatResult::default()
+    };
+
+    match options.output {
+        _ if options.code_only() => {}
+        OutputFormat::Text if result.content.is_empty() => println!("No content in response"),
+        OutputFormat::Text if markdown_enabled(options) => {
+            print!("{}", render_markdown(&result.content))
+        }
+        OutputFormat::Text => println!("{}", result.content),
+        _ => print_json(&result, options.output)?,
+    }
+    Ok(result)
+}
+
+pub type OnDelta<'a> = dyn FnMut(&str) -> Result<(), Box<dyn Error>> + 'a;
+
+pub fn parse_chat_line(
+    line: &[u8],
+    result: &mut ChatResult,
+    on_delta: &mut OnDelta<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let line_str = std::str::from_utf8(line).map_err(|_| "Invalid UTF-8 in response")?;
+    let line = line_str.trim_start_matches("data: ").trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    if let Ok(api_response) = serde_json::from_str::<ChatApiResponse>(line) {
+        if api_response.model.is_some() {
+            result.model = api_response.model;
+        }
+        if api_response.usage.is_some() {
+            result.usage = api_response.usage;
+        }
+        for choice in api_response.choices {
+            if choice.finish_reason.is_some() {
+                result.finish_reason = choice.finish_reason;
+            }
+            if let Some(content) = choice.delta.content {
+                on_delta(&content)?;
+                result.content.push_str(&content);
+            }
+        }
+    }
     Ok(())
 }
 
-pub async fn process_chat_response(response: reqwest::Response) -> Result<(), Box<dyn Error>> {
+// Reads a streamed chat reply, calling `on_delta` with each piece of content. The stream
+// stops early, marked as interrupted, if `cancel` fires.
+pub async fn read_chat_stream(
+    response: reqwest::Response,
+    options: &CliOptions,
+    cancel: Option<&interrupt::CancelGuard>,
+    on_delta: &mut OnDelta<'_>,
+) -> Result<ChatResult, Box<dyn Error>> {
     let mut stream = response.bytes_stream();
     let mut buffer = Vec::with_capacity(1024);
+    let mut result = ChatResult::default();
+    let cancelled = async {
+        match cancel {
+            Some(cancel) => cancel.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(cancelled);
 
-    while let Some(item) = stream.next().await {
-        let chunk = item?;
+    loop {
+        let next = tokio::select! {
+            _ = &mut cancelled => {
+                result.interrupted = true;
+                break;
+            }
+            next = with_timeout(TimeoutPhase::Idle, options.timeouts.idle, stream.next()) => next,
+        };
+        let item = match next {
+            Ok(Some(item)) => item.map_err(|e| e.to_string()),
+            Ok(None) => break,
+            Err(timeout) => Err(timeout.to_string()),
+        };
+        let chunk = item.map_err(|message| StreamInterrupted {
+            message,
+            emitted_tokens: !result.content.is_empty(),
+        })?;
         buffer.extend(chunk);
 
         let mut start = 0;
         while let Some(end) = buffer[start..].iter().position(|&b| b == b'\n') {
-            let line = &buffer[start..start + end];
+            parse_chat_line(&buffer[start..start + end], &mut result, on_delta)?;
             start += end + 1;
+        }
+        buffer.drain(0..start);
+    }
+    drop(stream);
+    if !result.interrupted {
+        parse_chat_line(&buffer, &mut result, on_delta)?;
+    }
+    Ok(result)
+}
 
-            let line_str = std::str::from_utf8(line).map_err(|_| "Invalid UTF-8 in response")?;
-            let line = line_str.trim_start_matches("data: ").trim();
-            if !line.is_empty() {
-                match serde_json::from_str::<ChatApiResponse>(line) {
-                    Ok(api_response) => {
-                        for choice in api_response.choices {
-                            if let Some(content) = choice.delta.content {
-                                print!("{}", content);
-                                io::stdout().flush().map_err(|e| {
-                                    io::Error::new(
-                                        io::ErrorKind::Other,
-                                        format!("Failed to flush stdout: {}", e),
-                                    )
-                                })?;
-                            }
-                        }
-                    }
-                    Err(_) => {}
+pub async fn process_chat_response(
+    response: reqwest::Response,
+    options: &CliOptions,
+) -> Result<ChatResult, Box<dyn Error>> {
+    let mut printer = StreamPrinter::new(options);
+    let cancel = interrupt::arm();
+    let result = read_chat_stream(response, options, Some(&cancel), &mut |content| {
+        printer.delta(content)
+    })
+    .await;
+    drop(cancel);
+    let result = result?;
+
+    printer.finish(&result)?;
+    if result.interrupted && options.output == OutputFormat::Text {
+        eprintln!("{}", "[interrupted]".dimmed());
+    }
+    Ok(result)
+}
+
+pub async fn process_dalle_response(
+    response: reqwest::Response,
+    options: &CliOptions,
+) -> Result<DalleApiResponse, Box<dyn Error>> {
+    let response_body = response.text().await?;
+    let api_response: DalleApiResponse = serde_json::from_str(&response_body)?;
+    match options.output {
+        OutputFormat::Text => {
+            for image_gen in api_response.data.iter() {
+                println!("Generated image URL: {:#?}", image_gen.url);
+            }
+        }
+        OutputFormat::Json => print_json(&json!({ "images": api_response.data }), options.output)?,
+        OutputFormat::Jsonl => {
+            for image_gen in api_response.data.iter() {
+                print_json(image_gen, options.output)?;
+            }
+        }
+    }
+    Ok(api_response)
+}
+
+pub fn output_code_blocks(
+    content: &str,
+    options: &CliOptions,
+) -> Result<Vec<CodeBlock>, Box<dyn Error>> {
+    let blocks = select_code_blocks(extract_code_blocks(content), options.code_index)?;
+    let paths = match &options.save_code {
+        Some(dir) => save_code_blocks(&blocks, dir)?,
+        None => Vec::new(),
+    };
+    for path in &paths {
+        eprintln!("Saved code block to {}", path.display());
+    }
+
+    if options.code_only() {
+        match options.output {
+            OutputFormat::Text => {
+                let code: Vec<&str> = blocks.iter().map(|block| block.code.as_str()).collect();
+                print!("{}", code.join("\n"));
+            }
+            OutputFormat::Json => print_json(&blocks, options.output)?,
+            OutputFormat::Jsonl => {
+                for block in &blocks {
+                    print_json(block, options.output)?;
                 }
             }
         }
-        buffer.drain(0..start);
     }
-    println!();
+    Ok(blocks)
+}
+
+pub fn process_tokens_command(args: &[String], options: &CliOptions) -> Result<(), Box<dyn Error>> {
+    let model = options.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    let text = match &args[2..] {
+        [] if io::stdin().is_terminal() => {
+            return Err(GptError::invalid_input("Usage: gpt tokens <text|file>").into())
+        }
+        [] => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| GptError::io(format!("Failed to read stdin: {}", e)))?;
+            text
+        }
+        [path] if Path::new(path).is_file() => fs::read_to_string(path)
+            .map_err(|e| GptError::io(format!("Failed to read {}: {}", path, e)))?,
+        words => words.join(" "),
+    };
+
+    let encoding = Encoding::for_model(model);
+    let count = encoding.count(&text);
+    let window = tokens::context_window(model);
+    match options.output {
+        OutputFormat::Text => match window {
+            Some(window) => println!(
+                "{} tokens ({}, {} has a {} token context window)",
+                count,
+                encoding.name(),
+                model,
+                window
+            ),
+            None => println!("{} tokens ({})", count, encoding.name()),
+        },
+        format => print_json(
+            &json!({
+                "tokens": count,
+                "model": model,
+                "encoding": encoding,
+                "context_window": window,
+            }),
+            format,
+        )?,
+    }
     Ok(())
 }
 
-pub async fn process_dalle_response(response: reqwest::Response) -> Result<(), Box<dyn Error>> {
-    let response_body = response.text().await?;
-    let api_response: DalleApiResponse = serde_json::from_str(&response_body)?;
-    for image_gen in api_response.data.iter() {
-        println!("Generated image URL: {:#?}", image_gen.url);
+pub fn process_usage_command(options: &CliOptions) -> Result<(), Box<dyn Error>> {
+    let ledger = options
+        .usage_ledger
+        .clone()
+        .unwrap_or_else(usage::ledger_path);
+    let entries = usage::read_ledger(&ledger)?;
+    let now = usage::now();
+    let since = options.since.map_or_else(
+        || usage::month_start(now),
+        |period| now.saturating_sub(period.as_secs()),
+    );
+    let rows = usage::summarize(&entries, since, options.group_by);
+    let total = usage::total(&rows);
+
+    if options.output != OutputFormat::Text {
+        return print_json(
+            &json!({
+                "since": usage::format_day(since),
+                "by": options.group_by.name(),
+                "rows": rows,
+                "total": total,
+            }),
+            options.output,
+        );
+    }
+
+    println!(
+        "Usage since {} by {}",
+        usage::format_day(since),
+        options.group_by.name()
+    );
+    if rows.is_empty() {
+        println!("No requests recorded.");
+        return Ok(());
+    }
+    let width = rows
+        .iter()
+        .map(|row| row.key.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(options.group_by.name().len());
+    let header = format!(
+        "{:<width$}  {:>8}  {:>12}  {:>12}  {:>6}  {:>10}",
+        options.group_by.name(),
+        "requests",
+        "prompt",
+        "completion",
+        "images",
+        "cost",
+        width = width
+    );
+    println!("{}", header.bold());
+    for row in rows.iter().chain(std::iter::once(&total)) {
+        println!(
+            "{:<width$}  {:>8}  {:>12}  {:>12}  {:>6}  {:>10}",
+            row.key,
+            row.requests,
+            row.prompt_tokens,
+            row.completion_tokens,
+            row.images,
+            format!("${:.4}", row.cost),
+            width = width
+        );
     }
     Ok(())
 }
@@ -198,59 +540,723 @@ pub async fn create_request_type_and_url(
     Ok((request_type, api_url))
 }
 
+pub fn record_request(
+    options: &CliOptions,
+    cache: CacheStatus,
+    started: Instant,
+    entry: impl FnOnce(&UsageLedger) -> UsageEntry,
+) {
+    let ledger = match UsageLedger::open(options) {
+        Ok(ledger) => ledger,
+        Err(e) => return print_warning(&format!("Failed to record usage: {}", e)),
+    };
+    let mut entry = entry(&ledger);
+    if cache == CacheStatus::Hit {
+        entry.cost = 0.0;
+    } else if let Err(e) = ledger.record(&entry) {
+        print_warning(&format!("Failed to record usage: {}", e));
+    }
+    if options.stats {
+        print_stats(
+            &RequestStats::new(&entry, started.elapsed(), cache),
+            options.output,
+        );
+    }
+}
+
+pub fn chat_usage(
+    usage: Option<ChatUsage>,
+    content: &str,
+    model: &str,
+    messages: &[ChatMessageRole],
+) -> ChatUsage {
+    usage.unwrap_or_else(|| {
+        let prompt_tokens = tokens::count_message_tokens(messages, model) as u32;
+        let completion_tokens = Encoding::for_model(model).count(content) as u32;
+        ChatUsage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    })
+}
+
+pub fn enforce_budget<S: AsRef<str>>(
+    options: &CliOptions,
+    models: &[S],
+) -> Result<(), Box<dyn Error>> {
+    let ledger = UsageLedger::open(options)?;
+    ledger.check_budget(options.budget, usage::now())?;
+    for model in models {
+        ledger.check_priced(options.budget, model.as_ref())?;
+    }
+    Ok(())
+}
+
+fn report_retry(reason: &str, delay: Duration, attempt: u32, policy: &RetryPolicy) {
+    eprintln!(
+        "{}, retrying in {:.1}s (attempt {}/{})",
+        reason,
+        delay.as_secs_f64(),
+        attempt,
+        policy.max_retries
+    );
+}
+
+pub async fn post_openai_request<T: Serialize + ?Sized>(
+    client: &Client,
+    api_url: &str,
+    body: &T,
+    options: &CliOptions,
+) -> Result<reqwest::Response, Box<dyn Error>> {
+    post_request(client, api_url, build_headers, body, options).await
+}
+
+// Posts with retries, recording or replaying through the cassette if one is open.
+pub async fn post_request<T: Serialize + ?Sized>(
+    client: &Client,
+    api_url: &str,
+    headers: fn() -> Result<HeaderMap, Box<dyn Error>>,
+    body: &T,
+    options: &CliOptions,
+) -> Result<reqwest::Response, Box<dyn Error>> {
+    let policy = RetryPolicy::new(options.max_retries);
+    let mut body_value = serde_json::to_value(body)?;
+    redact::redact_request(&mut body_value, options)?;
+    let streaming = body_value
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let mut attempt = 0;
+
+    loop {
+        let sent = match options.cassette.as_ref() {
+            Some(cassette) if cassette.is_replay() => {
+                Ok(cassette.next_response(api_url, &body_value)?)
+            }
+            cassette => {
+                let headers = headers()?;
+                let sent =
+                    send_request(client, api_url, headers, &body_value, streaming, options).await;
+                match (sent, cassette) {
+                    (Ok(response), Some(cassette)) => {
+                        Ok(cassette.capture(api_url, &body_value, response).await?)
+                    }
+                    (sent, _) => sent,
+                }
+            }
+        };
+
+        let (reason, delay) = match sent {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                if !is_retryable_status(status) || attempt >= policy.max_retries {
+                    let error_message = response.text().await.unwrap_or_default();
+                    return Err(GptError::from_api_response(status, &error_message).into());
+                }
+                let hint = if status == StatusCode::TOO_MANY_REQUESTS {
+                    rate_limit_delay(response.headers())
+                } else {
+                    retry_after_delay(response.headers())
+                };
+                let Some(delay) = policy.delay_for(attempt, hint) else {
+                    let error_message = response.text().await.unwrap_or_default();
+                    let context = format!(
+                        "Not retrying; the server asked to wait {}s, more than {}s",
+                        hint.unwrap_or_default().as_secs(),
+                        policy.max_hint.as_secs()
+                    );
+                    return Err(GptError::from_api_response(status, &error_message)
+                        .with_context(&context)
+                        .into());
+                };
+                (format!("Request failed with status code {}", status), delay)
+            }
+            Err((message, retryable)) => {
+                if !retryable || attempt >= policy.max_retries {
+                    return Err(GptError::network(message).into());
+                }
+                (message, policy.backoff_delay(attempt))
+            }
+        };
+
+        attempt += 1;
+        report_retry(&reason, delay, attempt, &policy);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn send_request<T: Serialize + ?Sized>(
+    client: &Client,
+    api_url: &str,
+    headers: HeaderMap,
+    body: &T,
+    streaming: bool,
+    options: &CliOptions,
+) -> Result<reqwest::Response, (String, bool)> {
+    let request = client.post(api_url).headers(headers).json(body).send();
+    // Non-streaming replies only send headers once generation is done, so they are
+    // bounded by the total budget of the caller instead.
+    if streaming {
+        with_timeout(
+            TimeoutPhase::FirstByte,
+            options.timeouts.first_byte,
+            request,
+        )
+        .await
+        .map_err(|e| (e.to_string(), true))
+        .and_then(|sent| sent.map_err(|e| describe_send_error(&e, options)))
+    } else {
+        request.await.map_err(|e| describe_send_error(&e, options))
+    }
+}
+
+fn describe_send_error(error: &reqwest::Error, options: &CliOptions) -> (String, bool) {
+    if error.is_connect() && error.is_timeout() {
+        let timeout = TimeoutError {
+            phase: TimeoutPhase::Connect,
+            after: options.timeouts.connect,
+        };
+        return (timeout.to_string(), true);
+    }
+    (
+        format!("Failed to send request to OpenAI: {}", error),
+        is_retryable_error(error),
+    )
+}
+
+pub async fn ensure_success(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, Box<dyn Error>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let error_message = response.text().await.unwrap_or_default();
+    Err(GptError::from_api_response(status, &error_message).into())
+}
+
+pub async fn send_openai_request(
+    request: reqwest::RequestBuilder,
+    options: &CliOptions,
+) -> Result<reqwest::Response, Box<dyn Error>> {
+    let request = async {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| GptError::network(describe_send_error(&e, options).0))?;
+        ensure_success(response).await
+    };
+    with_timeout(TimeoutPhase::Total, options.timeouts.total, request).await?
+}
+
+async fn fetch_response(
+    client: &Client,
+    api_url: &str,
+    request_body: &Value,
+    spinner_color: &str,
+    options: &CliOptions,
+) -> Result<reqwest::Response, Box<dyn Error>> {
+    let spinner = create_spinner(spinner_color, "Processing request...".to_string());
+    let result = post_openai_request(client, api_url, request_body, options).await;
+    spinner.finish_and_clear();
+    result
+}
+
+async fn fetch_or_replay(
+    client: &Client,
+    api_url: &str,
+    request_body: &Value,
+    spinner_color: &str,
+    options: &CliOptions,
+    cache: Option<(&ResponseCache, &str)>,
+) -> Result<(reqwest::Response, CacheStatus), Box<dyn Error>> {
+    let Some((cache, key)) = cache else {
+        let response =
+            fetch_response(client, api_url, request_body, spinner_color, options).await?;
+        return Ok((response, CacheStatus::Off));
+    };
+    if let Some(cached) = cache.get(key) {
+        return Ok((cached.into_response()?, CacheStatus::Hit));
+    }
+    let response = fetch_response(client, api_url, request_body, spinner_color, options).await?;
+    Ok((response, CacheStatus::Miss))
+}
+
+async fn cache_body(
+    response: reqwest::Response,
+    status: CacheStatus,
+    api_url: &str,
+    cache: Option<(&ResponseCache, &str)>,
+) -> Result<reqwest::Response, Box<dyn Error>> {
+    let (Some((cache, key)), CacheStatus::Miss) = (cache, status) else {
+        return Ok(response);
+    };
+    let body = response.text().await?;
+    if let Err(e) = cache.store_body(key, api_url, &body) {
+        print_warning(&format!("Failed to cache response: {}", e));
+    }
+    CachedResponse {
+        body: Some(body),
+        ..CachedResponse::default()
+    }
+    .into_response()
+}
+
 pub async fn make_openai_request(
     client: &Client,
     request_type: RequestType,
     api_url: &str,
+    options: &CliOptions,
 ) -> Result<(), Box<dyn Error>> {
-    let headers = build_headers()?;
     let spinner_color = match &request_type {
         RequestType::Chat(_) => "green",
         RequestType::Vision(_) => "magenta",
         RequestType::Dalle(_) => "red",
     };
-    let spinner = create_spinner(spinner_color, "Processing request...".to_string());
 
-    let request_body = match &request_type {
+    let mut request_body = match &request_type {
         RequestType::Chat(body) => serde_json::to_value(body)?,
         RequestType::Vision(body) => serde_json::to_value(body)?,
         RequestType::Dalle(body) => serde_json::to_value(body)?,
     };
+    redact::redact_request(&mut request_body, options)?;
+    let response_cache = ResponseCache::open(options)?;
+    let key = cache_key(api_url, &request_body);
+    let cache = response_cache.as_ref().map(|cache| (cache, key.as_str()));
+    let started = Instant::now();
 
-    let response = client
-        .post(api_url)
-        .headers(headers)
-        .json(&request_body)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request to OpenAI: {}", e))?;
+    match &request_type {
+        RequestType::Chat(body) => {
+            let policy = RetryPolicy::new(options.max_retries);
+            let mut stream_attempt = 0;
+            loop {
+                let (response, status) = fetch_or_replay(
+                    client,
+                    api_url,
+                    &request_body,
+                    spinner_color,
+                    options,
+                    cache,
+                )
+                .await?;
+                let result = process_chat_response(response, options).await;
+                if let Ok(result) = &result {
+                    let usage = chat_usage(
+                        result.usage.clone(),
+                        &result.content,
+                        &body.model,
+                        &body.messages,
+                    );
+                    record_request(options, status, started, |ledger| {
+                        ledger.chat_entry(&body.model, &usage)
+                    });
+                    if let (Some((cache, key)), CacheStatus::Miss, false) =
+                        (cache, status, result.interrupted)
+                    {
+                        if let Err(e) = cache.store_stream(key, api_url, result) {
+                            print_warning(&format!("Failed to cache response: {}", e));
+                        }
+                    }
+                }
+                match result {
+                    Ok(result) if result.interrupted => return Err(GptError::Interrupted.into()),
+                    Ok(result) => {
+                        if options.wants_code() {
+                            output_code_blocks(&result.content, options)?;
+                        }
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        let retryable = e
+                            .downcast_ref::<StreamInterrupted>()
+                            .is_some_and(|interrupted| !interrupted.emitted_tokens);
+                        if !retryable || stream_attempt >= policy.max_retries {
+                            return Err(e);
+                        }
+                        let delay = policy.backoff_delay(stream_attempt);
+                        stream_attempt += 1;
+                        report_retry(&e.to_string(), delay, stream_attempt, &policy);
+                        tokio::time::sleep(delay).await;
+                    }
+                }
+            }
+        }
+        RequestType::Vision(body) => {
+            let request = async {
+                let (response, status) = fetch_or_replay(
+                    client,
+                    api_url,
+                    &request_body,
+                    spinner_color,
+                    options,
+                    cache,
+                )
+                .await?;
+                let response = cache_body(response, status, api_url, cache).await?;
+                Ok::<_, Box<dyn Error>>((process_vision_response(response, options).await?, status))
+            };
+            let (result, status) =
+                with_timeout(TimeoutPhase::Total, options.timeouts.total, request).await??;
+            let usage = result.usage.clone().unwrap_or_default();
+            record_request(options, status, started, |ledger| {
+                ledger.chat_entry(&body.model, &usage)
+            });
+            if options.wants_code() {
+                output_code_blocks(&result.content, options)?;
+            }
+        }
+        RequestType::Dalle(body) => {
+            let request = async {
+                let (response, status) = fetch_or_replay(
+                    client,
+                    api_url,
+                    &request_body,
+                    spinner_color,
+                    options,
+                    cache,
+                )
+                .await?;
+                let response = cache_body(response, status, api_url, cache).await?;
+                Ok::<_, Box<dyn Error>>((process_dalle_response(response, options).await?, status))
+            };
+            let (images, status) =
+                with_timeout(TimeoutPhase::Total, options.timeouts.total, request).await??;
+            record_request(options, status, started, |ledger| {
+                ledger.image_entry(
+                    &body.model,
+                    images.data.len() as u32,
+                    &body.size,
+                    &body.quality,
+                )
+            });
+        }
+    }
 
-    spinner.finish_and_clear();
+    Ok(())
+}
 
-    if response.status().is_success() {
-        match &request_type {
-            RequestType::Chat(_) => process_chat_response(response).await?,
-            RequestType::Vision(_) => process_vision_response(response).await?,
-            RequestType::Dalle(_) => process_dalle_response(response).await?,
-        }
+pub async fn send_chat_completion(
+    client: &Client,
+    api_url: &str,
+    request: &OpenAiChatRequestBody,
+    options: &CliOptions,
+) -> Result<ChatCompletionResponse, Box<dyn Error>> {
+    let request = async {
+        let response = post_openai_request(client, api_url, request, options).await?;
+        response
+            .json::<ChatCompletionResponse>()
+            .await
+            .map_err(|e| {
+                GptError::invalid_reply(format!("Failed to parse chat completion: {}", e)).into()
+            })
+    };
+    with_timeout(TimeoutPhase::Total, options.timeouts.total, request).await?
+}
+
+// Runs a non-streaming chat request through the usual context, budget and usage checks and
+// returns the reply text.
+pub async fn complete_chat(
+    client: &Client,
+    api_url: &str,
+    request: &mut OpenAiChatRequestBody,
+    options: &CliOptions,
+) -> Result<String, Box<dyn Error>> {
+    request.stream = false;
+    prepare_chat_request(request, options)?;
+    enforce_budget(options, &[&request.model])?;
+    let started = Instant::now();
+    let completion = send_chat_completion(client, api_url, request, options).await?;
+    let content = completion
+        .choices
+        .first()
+        .and_then(|c| c.message.content.clone())
+        .unwrap_or_default();
+    let usage = chat_usage(
+        completion.usage,
+        &content,
+        &request.model,
+        &request.messages,
+    );
+    record_request(options, CacheStatus::Off, started, |ledger| {
+        ledger.chat_entry(&request.model, &usage)
+    });
+    Ok(content)
+}
+
+pub async fn cached_chat_completion(
+    client: &Client,
+    api_url: &str,
+    request: &OpenAiChatRequestBody,
+    options: &CliOptions,
+    cache: Option<&ResponseCache>,
+) -> Result<(ChatCompletionResponse, CacheStatus), Box<dyn Error>> {
+    let Some(cache) = cache else {
+        let completion = send_chat_completion(client, api_url, request, options).await?;
+        return Ok((completion, CacheStatus::Off));
+    };
+    let mut body = serde_json::to_value(request)?;
+    redact::redact_request(&mut body, options)?;
+    let key = cache_key(api_url, &body);
+    let cached = cache
+        .get(&key)
+        .and_then(|cached| serde_json::from_str(&cached.body?).ok());
+    if let Some(completion) = cached {
+        return Ok((completion, CacheStatus::Hit));
+    }
+    let completion = send_chat_completion(client, api_url, request, options).await?;
+    if let Err(e) = cache.store_body(&key, api_url, &serde_json::to_string(&completion)?) {
+        print_warning(&format!("Failed to cache response: {}", e));
+    }
+    Ok((completion, CacheStatus::Miss))
+}
+
+pub fn load_json_schema(schema_path: &str) -> Result<Value, Box<dyn Error>> {
+    let contents = fs::read_to_string(schema_path)
+        .map_err(|_| GptError::io(format!("Failed to read JSON schema file: {}", schema_path)))?;
+    let schema = serde_json::from_str(&contents).map_err(|e| {
+        GptError::invalid_input(format!("Invalid JSON schema in {}: {}", schema_path, e))
+    })?;
+    Ok(schema)
+}
+
+pub fn schema_name(schema_path: &str, schema: &Value) -> String {
+    let name = schema
+        .get("title")
+        .and_then(Value::as_str)
+        .or_else(|| Path::new(schema_path).file_stem().and_then(|s| s.to_str()))
+        .unwrap_or("response");
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect();
+    if name.is_empty() {
+        "response".to_string()
     } else {
-        eprintln!("Failed with status code: {}", response.status());
-        if let Ok(error_message) = response.text().await {
-            eprintln!("Response error message: {}", error_message);
+        name
+    }
+}
+
+pub fn build_structured_chat_request(
+    args: &[String],
+    model: &str,
+    name: String,
+    schema: Value,
+) -> OpenAiChatRequestBody {
+    let mut request = build_chat_request(args, model);
+    request.stream = false;
+    // The reply is validated locally, so user schemas don't have to satisfy strict mode.
+    request.response_format = Some(ResponseFormat {
+        format_type: "json_schema".to_string(),
+        json_schema: JsonSchemaFormat {
+            name,
+            schema,
+            strict: false,
+        },
+    });
+    request
+}
+
+// Models known to reject json_schema response formats. Unknown names are let through, since
+// they may be newer models or served by another provider.
+pub fn supports_structured_outputs(model: &str) -> bool {
+    let model = model.to_lowercase();
+    let model = model.strip_prefix("ft:").unwrap_or(&model);
+    !(model.starts_with("gpt-3.5")
+        || (model.starts_with("gpt-4")
+            && !model.starts_with("gpt-4o")
+            && !model.starts_with("gpt-4.1"))
+        || model.starts_with("gpt-4o-2024-05-13")
+        || model.starts_with("o1-mini")
+        || model.starts_with("o1-preview"))
+}
+
+// The schemas of built-in commands are written to satisfy strict mode.
+pub fn structured_chat_request(
+    model: &str,
+    system: &str,
+    prompt: String,
+    name: &str,
+    schema: Value,
+) -> OpenAiChatRequestBody {
+    let mut request = chat_request(model, system, prompt);
+    request.stream = false;
+    request.response_format = Some(ResponseFormat {
+        format_type: "json_schema".to_string(),
+        json_schema: JsonSchemaFormat {
+            name: name.to_string(),
+            schema,
+            strict: true,
+        },
+    });
+    request
+}
+
+pub async fn run_structured_chat(
+    client: &Client,
+    api_url: &str,
+    mut request: OpenAiChatRequestBody,
+    options: &CliOptions,
+) -> Result<Value, Box<dyn Error>> {
+    let schema = request
+        .response_format
+        .as_ref()
+        .map(|format| format.json_schema.schema.clone())
+        .ok_or("Structured chat requests require a response format")?;
+    if !supports_structured_outputs(&request.model) {
+        return Err(GptError::invalid_input(format!(
+            "{} does not support JSON schema replies; use a model like {} or {}",
+            request.model, STRUCTURED_MODEL, GPT4_STRUCTURED_MODEL
+        ))
+        .into());
+    }
+    schema::check_supported(&schema)
+        .map_err(|e| GptError::invalid_input(format!("Unsupported JSON schema {}", e)))?;
+    let attempts = if options.schema_retry { 2 } else { 1 };
+
+    let cache = ResponseCache::open(options)?;
+    let mut last_error = None;
+    for _ in 0..attempts {
+        prepare_chat_request(&mut request, options)?;
+        let started = Instant::now();
+        let (completion, status) =
+            cached_chat_completion(client, api_url, &request, options, cache.as_ref()).await?;
+        let content = completion
+            .choices
+            .first()
+            .and_then(|c| c.message.content.clone())
+            .unwrap_or_default();
+        let usage = chat_usage(
+            completion.usage,
+            &content,
+            &request.model,
+            &request.messages,
+        );
+        record_request(options, status, started, |ledger| {
+            ledger.chat_entry(&request.model, &usage)
+        });
+
+        let validation = serde_json::from_str::<Value>(&content)
+            .map_err(|e| schema::SchemaError {
+                path: String::new(),
+                message: format!("response is not valid JSON ({})", e),
+            })
+            .and_then(|value| schema::validate(&schema, &value).map(|_| value));
+
+        match validation {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                request.messages.push(ChatMessageRole {
+                    role: "assistant".to_string(),
+                    content,
+                });
+                request.messages.push(ChatMessageRole {
+                    role: "user".to_string(),
+                    content: format!(
+                        "Your reply did not match the JSON schema {}. Reply again with JSON that matches the schema.",
+                        e
+                    ),
+                });
+                last_error = Some(e);
+            }
         }
-        return Err("Failed to get a valid response".into());
     }
 
+    Err(GptError::invalid_reply(format!(
+        "Response does not match JSON schema {}",
+        last_error.map_or_else(String::new, |e| e.to_string())
+    ))
+    .into())
+}
+
+pub async fn process_structured_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+    schema_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let model = match args[1].as_str() {
+        CMD_VISION | CMD_DALLE => {
+            return Err(
+                GptError::invalid_input("--json-schema is only supported for chat prompts").into(),
+            )
+        }
+        CMD_GPT4 => GPT4_STRUCTURED_MODEL,
+        _ => STRUCTURED_MODEL,
+    };
+    let model = options.model.as_deref().unwrap_or(model);
+    enforce_budget(options, &[model])?;
+    let schema = load_json_schema(schema_path)?;
+    let request =
+        build_structured_chat_request(args, model, schema_name(schema_path, &schema), schema);
+
+    let spinner = create_spinner("green", "Processing request...".to_string());
+    let result = run_structured_chat(client, GPT_API_URL, request, options).await;
+    spinner.finish_and_clear();
+
+    let value = result?;
+    match options.output {
+        OutputFormat::Jsonl => print_json(&value, options.output)?,
+        _ => print_json(&value, OutputFormat::Json)?,
+    }
     Ok(())
 }
 
 pub async fn process_command(
     client: &reqwest::Client,
     args: &[String],
+    options: &CliOptions,
 ) -> Result<(), Box<dyn Error>> {
-    let (request_type, api_url) = create_request_type_and_url(args)
+    match args[1].as_str() {
+        CMD_TOKENS => return process_tokens_command(args, options),
+        CMD_USAGE => return process_usage_command(options),
+        CMD_BATCH => return batch::process_batch_command(client, args, options).await,
+        CMD_COMMIT => return commit::process_commit_command(client, args, options).await,
+        CMD_REVIEW => return review::process_review_command(client, args, options).await,
+        CMD_COMPLETIONS => return completions::process_completions_command(args),
+        CMD_SHELL_INIT => return completions::process_shell_init_command(args),
+        CMD_EDIT => return edit::process_edit_command(client, args, options).await,
+        CMD_WATCH => return watch::process_watch_command(client, args, options).await,
+        CMD_COMPARE => return compare::process_compare_command(client, args, options).await,
+        CMD_EVAL => return eval::process_eval_command(client, args, options).await,
+        CMD_SERVE => return serve::process_serve_command(client, options).await,
+        CMD_SH => return shell::process_sh_command(client, args, options).await,
+        CMD_EXPLAIN_CMD => return shell::process_explain_cmd_command(client, args, options).await,
+        CMD_RUN => return templates::process_run_command(client, args, options).await,
+        CMD_TEMPLATES => return templates::process_templates_command(args, options),
+        _ => {}
+    }
+    if let Some(schema_path) = &options.json_schema {
+        return process_structured_command(client, args, options, schema_path).await;
+    }
+
+    let (mut request_type, api_url) = create_request_type_and_url(args)
         .await
-        .map_err(|e| format!("Failed to create request: {}", e))?;
+        .map_err(|e| with_context(e, "Failed to create request"))?;
 
-    make_openai_request(client, request_type, api_url).await
+    if let RequestType::Chat(body) = &mut request_type {
+        if let Some(model) = &options.model {
+            body.model = model.clone();
+        }
+        prepare_chat_request(body, options)?;
+        body.stream_options = Some(StreamOptions {
+            include_usage: true,
+        });
+    }
+
+    let model = match &request_type {
+        RequestType::Chat(body) => &body.model,
+        RequestType::Vision(body) => &body.model,
+        RequestType::Dalle(body) => &body.model,
+    };
+    enforce_budget(options, &[model])?;
+    make_openai_request(client, request_type, api_url, options).await
 }
@@ -1,3 +1,4 @@
+use crate::chat::ChatUsage;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
@@ -8,11 +9,14 @@ pub struct VisionMessage {
 #[derive(Debug, Deserialize)]
 pub struct VisionChoice {
     pub message: VisionMessage,
+    pub finish_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct VisionApiResponse {
+    pub model: Option<String>,
     pub choices: Vec<VisionChoice>,
+    pub usage: Option<ChatUsage>,
 }
 
 #[derive(Debug, Serialize)]
@@ -0,0 +1,252 @@
+use crate::chat::{OpenAiChatRequestBody, StreamOptions};
+use crate::constants::{
+    RequestType, DEFAULT_MODEL, DEFAULT_SYSTEM_PROMPT, GPT_API_URL, WATCH_DEBOUNCE_MS,
+    WATCH_MAX_FILES, WATCH_MAX_FILE_BYTES,
+};
+use crate::error::GptError;
+use crate::options::CliOptions;
+use crate::output::{print_error, print_warning};
+use crate::templates::{build_template_request, find_template, Template};
+use crate::utils::{chat_request, enforce_budget, make_openai_request, prepare_chat_request};
+use colored::Colorize;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use reqwest::Client;
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+pub enum WatchPrompt {
+    Template(Template),
+    Prompt(String),
+}
+
+enum Outcome {
+    Finished(Result<(), Box<dyn Error>>),
+    Changed(Option<BTreeSet<PathBuf>>),
+}
+
+pub fn read_tail(path: &Path, max_bytes: usize) -> Option<String> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.len() <= max_bytes {
+        return Some(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    let tail = &bytes[bytes.len() - max_bytes..];
+    // Start on a line boundary so the first line isn't cut in half.
+    let start = tail.iter().position(|&b| b == b'\n').map_or(0, |i| i + 1);
+    Some(format!(
+        "[... {} earlier bytes omitted]\n{}",
+        bytes.len() - tail.len() + start,
+        String::from_utf8_lossy(&tail[start..])
+    ))
+}
+
+pub fn attach_files(files: &[PathBuf]) -> String {
+    let mut attached = String::new();
+    for path in files {
+        let Some(contents) = read_tail(path, WATCH_MAX_FILE_BYTES) else {
+            continue;
+        };
+        attached.push_str(&format!("{}\n```\n{}", path.display(), contents));
+        if !contents.ends_with('\n') {
+            attached.push('\n');
+        }
+        attached.push_str("```\n\n");
+    }
+    attached
+}
+
+// Directories are expanded into the files below them, skipping hidden ones like .git.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return;
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    for entry in entries {
+        collect_files(&entry, files);
+    }
+}
+
+// The first run attaches every watched file; later runs only attach the files that changed.
+pub fn files_to_attach(watched: &[PathBuf], changed: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if changed.is_empty() {
+        for path in watched {
+            collect_files(path, &mut files);
+        }
+    } else {
+        files.extend(changed.iter().filter(|path| path.is_file()).cloned());
+    }
+    if files.len() > WATCH_MAX_FILES {
+        print_warning(&format!(
+            "Attaching only the first {} of {} files",
+            WATCH_MAX_FILES,
+            files.len()
+        ));
+        files.truncate(WATCH_MAX_FILES);
+    }
+    files
+}
+
+// Templates get the attached files as {{files}} and the changed paths as {{changed}}.
+pub fn build_watch_request(
+    prompt: &WatchPrompt,
+    files: &[PathBuf],
+    options: &CliOptions,
+) -> Result<OpenAiChatRequestBody, Box<dyn Error>> {
+    let attached = attach_files(files);
+    let mut request = match prompt {
+        WatchPrompt::Template(template) => {
+            let mut template = template.clone();
+            let changed: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+            template.vars.insert("files".to_string(), attached);
+            template
+                .vars
+                .insert("changed".to_string(), changed.join(", "));
+            build_template_request(&template, &[], options, &mut || {
+                Err(GptError::invalid_input("{{stdin}} can't be used with gpt watch").into())
+            })?
+        }
+        WatchPrompt::Prompt(prompt) => {
+            let model = options.model.as_deref().unwrap_or(DEFAULT_MODEL);
+            let mut request = chat_request(
+                model,
+                DEFAULT_SYSTEM_PROMPT,
+                format!("{}\n\n{}", prompt, attached).trim_end().to_string(),
+            );
+            request.stream_options = Some(StreamOptions {
+                include_usage: true,
+            });
+            request
+        }
+    };
+    prepare_chat_request(&mut request, options)?;
+    Ok(request)
+}
+
+pub fn start_watcher(
+    paths: &[PathBuf],
+) -> Result<(RecommendedWatcher, UnboundedReceiver<PathBuf>), Box<dyn Error>> {
+    let (sender, receiver) = unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            for path in event.paths {
+                let _ = sender.send(path);
+            }
+        }
+    })
+    .map_err(|e| GptError::io(format!("Failed to watch files: {}", e)))?;
+    for path in paths {
+        watcher
+            .watch(path, RecursiveMode::Recursive)
+            .map_err(|e| GptError::io(format!("Failed to watch {}: {}", path.display(), e)))?;
+    }
+    Ok((watcher, receiver))
+}
+
+// Waits for a change, then keeps collecting until no event arrives for `debounce`, so a
+// burst of writes triggers one run.
+pub async fn next_changes(
+    receiver: &mut UnboundedReceiver<PathBuf>,
+    debounce: Duration,
+) -> Option<BTreeSet<PathBuf>> {
+    let mut changed = BTreeSet::from([receiver.recv().await?]);
+    while let Ok(Some(path)) = tokio::time::timeout(debounce, receiver.recv()).await {
+        changed.insert(path);
+    }
+    Some(changed)
+}
+
+fn watch_prompt(options: &CliOptions) -> Result<WatchPrompt, Box<dyn Error>> {
+    match (&options.template, &options.prompt) {
+        (Some(name), None) => {
+            let dir = options
+                .templates_dir
+                .as_deref()
+                .ok_or_else(|| GptError::invalid_input("No template directory configured"))?;
+            Ok(WatchPrompt::Template(Template::load(&find_template(
+                dir, name,
+            )?)?))
+        }
+        (None, Some(prompt)) => Ok(WatchPrompt::Prompt(prompt.clone())),
+        _ => Err(GptError::invalid_input(
+            "Usage: gpt watch <path>... --template <name> | --prompt <text>",
+        )
+        .into()),
+    }
+}
+
+pub async fn process_watch_command(
+    client: &Client,
+    args: &[String],
+    options: &CliOptions,
+) -> Result<(), Box<dyn Error>> {
+    let paths: Vec<PathBuf> = args[2..].iter().map(PathBuf::from).collect();
+    if paths.is_empty() {
+        return Err(GptError::invalid_input(
+            "Usage: gpt watch <path>... --template <name> | --prompt <text>",
+        )
+        .into());
+    }
+    let prompt = watch_prompt(options)?;
+    let (_watcher, mut receiver) = start_watcher(&paths)?;
+    let debounce = Duration::from_millis(WATCH_DEBOUNCE_MS);
+
+    let mut changed = BTreeSet::new();
+    loop {
+        let files = files_to_attach(&paths, &changed);
+        let label = if changed.is_empty() {
+            format!("Watching {} path(s); press Ctrl-C to stop", paths.len())
+        } else {
+            let names: Vec<String> = changed.iter().map(|p| p.display().to_string()).collect();
+            format!("Changed: {}", names.join(", "))
+        };
+        eprintln!("{}", format!("── {} ──", label).dimmed());
+
+        let request = build_watch_request(&prompt, &files, options)?;
+        enforce_budget(options, &[&request.model])?;
+        // A change while the reply is streaming drops the request and starts over.
+        let outcome = tokio::select! {
+            result = make_openai_request(client, RequestType::Chat(request), GPT_API_URL, options) => {
+                Outcome::Finished(result)
+            }
+            changes = next_changes(&mut receiver, debounce) => Outcome::Changed(changes),
+        };
+        let changes = match outcome {
+            Outcome::Finished(result) => {
+                // Keep watching after failed requests, e.g. a network blip.
+                if let Err(e) = result {
+                    print_error(e.as_ref(), options.output);
+                }
+                next_changes(&mut receiver, debounce).await
+            }
+            Outcome::Changed(changes) => {
+                eprintln!("\n{}", "[files changed, restarting]".dimmed());
+                changes
+            }
+        };
+        match changes {
+            Some(changes) => changed = changes,
+            None => return Ok(()),
+        }
+    }
+}